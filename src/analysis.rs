@@ -0,0 +1,477 @@
+use std::ops::Range;
+use crate::lexer::{LexError, LexErrorKind, LiteralKind, Location, Span, Token, TokenKind};
+
+/// Line with the most characters and the number of tokens on it. The first line wins ties
+pub fn widest_line(tokens: &[(Token, Location)], input: &str) -> (usize, usize) {
+    let mut widest = 0;
+    let mut width = 0;
+    for (line, text) in input.lines().enumerate() {
+        let chars = text.chars().count();
+        if chars > width {
+            widest = line;
+            width = chars;
+        }
+    }
+    let count = tokens.iter().filter(|(_, location)| location.line == widest).count();
+    (widest, count)
+}
+
+/// Indices of every token of the given kind
+pub fn find_all(tokens: &[(Token, Location)], kind: TokenKind) -> Vec<usize> {
+    tokens.iter().enumerate().filter(|(_, (token, _))| token.kind() == kind).map(|(index, _)| index).collect()
+}
+
+/// Identifiers directly followed by `(` (or `()` when it's collapsed into `Unit`), which are most
+/// likely function calls
+pub fn call_sites(tokens: &[(Token, Location)]) -> Vec<(&str, Location)> {
+    tokens.windows(2)
+        .filter_map(|pair| match (&pair[0].0, &pair[1].0) {
+            (Token::Identifier(name), Token::LParen | Token::Unit) => Some((name.as_str(), pair[0].1)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Index range of the tokens a text edit over the byte range `edit` touches, i.e. those whose
+/// spans overlap it or end/start right at its edges. An edit that only touches whitespace gives
+/// an empty range at the position where new tokens would go
+pub fn affected_token_range(tokens: &[(Token, Span)], edit: Range<usize>) -> Range<usize> {
+    let first = tokens.partition_point(|(_, span)| span.end < edit.start);
+    let last = tokens.partition_point(|(_, span)| span.start <= edit.end);
+    first..last.max(first)
+}
+
+/// Deepest level of nested `()`, `{}`, `#{}`, `{{}}` and `[]` anywhere in the stream. A collapsed `Unit` counts
+/// as one level. Unbalanced closers are ignored rather than going below zero
+pub fn max_nesting_depth(tokens: &[(Token, Location)]) -> usize {
+    let mut depth: usize = 0;
+    let mut deepest = 0;
+    for (token, _) in tokens {
+        match token {
+            Token::LParen | Token::LBrace | Token::HashBrace | Token::LBraceBrace | Token::LBracket => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            Token::RParen | Token::RBrace | Token::RBraceBrace | Token::RBracket => depth = depth.saturating_sub(1),
+            Token::Unit => deepest = deepest.max(depth + 1),
+            _ => {}
+        }
+    }
+    deepest
+}
+
+/// Location of the first token of each top-level statement, i.e. after every `;` outside of braces.
+/// A trailing `;` doesn't start an empty statement
+pub fn statement_starts(tokens: &[(Token, Location)]) -> Vec<Location> {
+    let mut starts = Vec::new();
+    let mut depth: usize = 0;
+    let mut at_start = true;
+    for (token, location) in tokens {
+        if at_start && depth == 0 && *token != Token::Semi {
+            starts.push(*location);
+            at_start = false;
+        }
+        match token {
+            Token::LBrace | Token::HashBrace | Token::LBraceBrace => depth += 1,
+            Token::RBrace | Token::RBraceBrace => depth = depth.saturating_sub(1),
+            Token::Semi if depth == 0 => at_start = true,
+            _ => {}
+        }
+    }
+    starts
+}
+
+/// Locations of literals and identifiers that directly follow another literal or identifier with
+/// nothing but whitespace or comments in between, like `1 2`
+pub fn adjacent_values(tokens: &[(Token, Location)]) -> Vec<Location> {
+    let is_value = |token: &Token| matches!(token, Token::Identifier(_) | Token::Literal(_));
+    tokens.windows(2)
+        .filter(|pair| is_value(&pair[0].0) && is_value(&pair[1].0))
+        .map(|pair| pair[1].1)
+        .collect()
+}
+
+/// Word spellings of operators and the canonical operator token each one stands for
+pub const OPERATOR_WORDS: &[(&str, Token)] = &[("and", Token::AndAnd), ("or", Token::OrOr)];
+
+/// Rewrites operators spelled as words to their canonical tokens, per `OPERATOR_WORDS`: `and` to
+/// `AndAnd` (`&&`) and `or` to `OrOr` (`||`). `operator_aliases` already resolve these while
+/// lexing, so this is for streams lexed without them, where the words come out as identifiers
+pub fn canonicalize_operators(tokens: &mut [(Token, Location)]) {
+    for (token, _) in tokens.iter_mut() {
+        let Token::Identifier(name) = token else {
+            continue;
+        };
+        if let Some((_, canonical)) = OPERATOR_WORDS.iter().find(|(word, _)| word == name) {
+            *token = canonical.clone();
+        }
+    }
+}
+
+/// Locations of char literals holding a control character, which is usually a mistake. The ones
+/// with a common escape (`\n`, `\t`, `\r` and `\0`) are allowed
+pub fn nonprintable_char_literals(tokens: &[(Token, Location)]) -> Vec<Location> {
+    tokens.iter()
+        .filter(|(token, _)| matches!(token, Token::Literal(LiteralKind::Char { value })
+            if value.is_control() && !matches!(value, '\n' | '\t' | '\r' | '\0')))
+        .map(|(_, location)| *location)
+        .collect()
+}
+
+/// Locations of identifiers repeating the identifier right before them, like `foo foo`, which is
+/// usually a copy-paste mistake
+pub fn duplicate_identifiers(tokens: &[(Token, Location)]) -> Vec<Location> {
+    tokens.windows(2)
+        .filter(|pair| matches!(&pair[0].0, Token::Identifier(_)) && pair[0].0 == pair[1].0)
+        .map(|pair| pair[1].1)
+        .collect()
+}
+
+/// Token kinds that still lex but are on their way out. Nothing is deprecated yet
+pub const DEPRECATED_TOKENS: &[TokenKind] = &[];
+
+/// Locations of every token whose kind is in `DEPRECATED_TOKENS`
+pub fn flag_deprecated_tokens(tokens: &[(Token, Location)]) -> Vec<Location> {
+    tokens.iter()
+        .filter(|(token, _)| DEPRECATED_TOKENS.contains(&token.kind()))
+        .map(|(_, location)| *location)
+        .collect()
+}
+
+/// The last token ending at or before the byte `offset`, e.g. the token left of an editor cursor
+pub fn token_before(tokens: &[(Token, Span)], offset: usize) -> Option<&(Token, Span)> {
+    let index = tokens.partition_point(|(_, span)| span.end <= offset);
+    index.checked_sub(1).map(|index| &tokens[index])
+}
+
+/// Guess whether the `<` at `lt_index` opens generic arguments, like `Vec<T>`, rather than being
+/// a comparison. It has to follow an identifier and be closed by a matching `>` with only names,
+/// `,`, `:` and nested generics in between
+pub fn is_likely_generic(tokens: &[(Token, Location)], lt_index: usize) -> bool {
+    if tokens.get(lt_index).map(|(token, _)| token) != Some(&Token::LessThan) {
+        return false;
+    }
+    if !matches!(lt_index.checked_sub(1).map(|index| &tokens[index].0), Some(Token::Identifier(_))) {
+        return false;
+    }
+    let mut depth = 0;
+    for (token, _) in &tokens[lt_index..] {
+        match token {
+            Token::LessThan => depth += 1,
+            Token::GreaterThan => {
+                depth -= 1;
+                if depth == 0 {
+                    return true;
+                }
+            }
+            Token::Identifier(_) | Token::Comma | Token::Colon => {}
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Identifiers directly after a `let` or `fn` keyword, i.e. where variables and functions are
+/// defined
+pub fn definitions(tokens: &[(Token, Location)]) -> Vec<(&str, Location)> {
+    tokens.windows(2)
+        .filter_map(|pair| match (&pair[0].0, &pair[1].0) {
+            (Token::Keyword(keyword), Token::Identifier(name)) if keyword == "let" || keyword == "fn" => Some((name.as_str(), pair[1].1)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Levenshtein distance between two token streams, comparing only token kinds so names, literal
+/// values and locations don't matter
+pub fn token_edit_distance(a: &[(Token, Location)], b: &[(Token, Location)]) -> usize {
+    // distances from the first `i` tokens of `a` to each prefix of `b`, one row at a time
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, (left, _)) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, (right, _)) in b.iter().enumerate() {
+            let substitution = diagonal + (left.kind() != right.kind()) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Guess whether the `@` at `index` binds a name to a pattern, like `x @ 1..10`, rather than
+/// starting an annotation. It has to follow an identifier and be followed by something that can
+/// start a pattern
+pub fn is_binding_at(tokens: &[(Token, Location)], index: usize) -> bool {
+    if tokens.get(index).map(|(token, _)| token) != Some(&Token::At) {
+        return false;
+    }
+    let after_name = matches!(index.checked_sub(1).map(|index| &tokens[index].0), Some(Token::Identifier(_)));
+    let before_pattern = matches!(tokens.get(index + 1).map(|(token, _)| token),
+        Some(Token::Identifier(_) | Token::Literal(_) | Token::Minus | Token::LParen | Token::LBracket | Token::LBrace | Token::Unit));
+    after_name && before_pattern
+}
+
+/// Guess whether the `&` at `index` takes a reference, like `&x`, rather than being a bitwise
+/// and. It's a reference unless it follows a value
+pub fn is_reference_amp(tokens: &[(Token, Location)], index: usize) -> bool {
+    if tokens.get(index).map(|(token, _)| token) != Some(&Token::And) {
+        return false;
+    }
+    !index.checked_sub(1).is_some_and(|index| tokens[index].0.is_value())
+}
+
+/// The tokens up to and including the first `;` outside of braces, or None if no statement is
+/// complete yet, e.g. while a REPL is still reading input
+pub fn first_statement(tokens: &[(Token, Location)]) -> Option<&[(Token, Location)]> {
+    let mut depth: usize = 0;
+    for (index, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Token::LBrace | Token::HashBrace | Token::LBraceBrace => depth += 1,
+            Token::RBrace | Token::RBraceBrace => depth = depth.saturating_sub(1),
+            Token::Semi if depth == 0 => return Some(&tokens[..=index]),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Check that the token at `index` is of one of the `kinds` the grammar allows there. Past the
+/// end of the tokens the error is reported at the last token
+pub fn expect_one_of(tokens: &[(Token, Location)], index: usize, kinds: &[TokenKind]) -> Result<(), LexError> {
+    let found = tokens.get(index).map(|(token, _)| token.kind());
+    if found.is_some_and(|found| kinds.contains(&found)) {
+        return Ok(());
+    }
+    let location = tokens.get(index).or(tokens.last()).map_or(Location { line: 0 }, |(_, location)| *location);
+    Err(LexError { kind: LexErrorKind::UnexpectedToken { found, expected: kinds.to_vec() }, location })
+}
+
+/// Guess whether the `:` at `index` starts a type annotation, like in `let x: Int` or a parameter
+/// of `fn f(x: Int)`, rather than another use of a colon such as in `{a: b}`. The colon has to
+/// follow a name that's declared by `let` or is a parameter directly inside `fn name(...)`
+pub fn is_type_annotation_colon(tokens: &[(Token, Location)], index: usize) -> bool {
+    let token = |index: usize| tokens.get(index).map(|(token, _)| token);
+    let is_keyword = |index: usize, word: &str| matches!(token(index), Some(Token::Keyword(keyword)) if keyword == word);
+    if token(index) != Some(&Token::Colon) || index == 0 || !matches!(token(index - 1), Some(Token::Identifier(_))) {
+        return false;
+    }
+    let name = index - 1;
+    if name > 0 && is_keyword(name - 1, "let") {
+        return true;
+    }
+    // find the `(` of the parameter list the name is directly in, if any
+    let mut depth = 0;
+    for open in (0..name).rev() {
+        match token(open) {
+            Some(Token::RParen | Token::RBracket | Token::RBrace) => depth += 1,
+            Some(Token::LParen) if depth == 0 => {
+                return open >= 2 && matches!(token(open - 1), Some(Token::Identifier(_))) && is_keyword(open - 2, "fn");
+            }
+            Some(Token::LParen | Token::LBracket | Token::LBrace | Token::HashBrace) if depth > 0 => depth -= 1,
+            Some(Token::LBracket | Token::LBrace | Token::HashBrace) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Number of leading whitespace characters on the line of each token, a tab counting as one
+pub fn indent_depths(input: &str, tokens: &[(Token, Location)]) -> Vec<usize> {
+    let depths: Vec<usize> = input.lines().map(|line| line.chars().take_while(|c| c.is_whitespace()).count()).collect();
+    tokens.iter().map(|(_, location)| depths.get(location.line).copied().unwrap_or(0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{tokenize, tokenize_spanned, tokenize_with_config, LexerConfig};
+    use std::collections::HashMap;
+
+    #[test]
+    fn widest_line_and_token_count() {
+        let input = "a = 1;\nlonger_name = a + 2;\nb;";
+        let tokens = tokenize(input).unwrap();
+        assert_eq!(widest_line(&tokens, input), (1, 6));
+    }
+
+    #[test]
+    fn find_all_plus() {
+        let tokens = tokenize("a + b + c").unwrap();
+        assert_eq!(find_all(&tokens, TokenKind::Plus), vec![1, 3]);
+        assert_eq!(find_all(&tokens, TokenKind::Minus), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn nested_call_sites() {
+        let tokens = tokenize("foo(bar())\nx = (y)").unwrap();
+        assert_eq!(call_sites(&tokens), vec![("foo", Location { line: 0 }), ("bar", Location { line: 0 })]);
+    }
+
+    #[test]
+    fn edit_inside_a_token() {
+        let tokens = tokenize_spanned("aa bbb cc").unwrap();
+        assert_eq!(affected_token_range(&tokens, 4..5), 1..2);
+    }
+
+    #[test]
+    fn edit_touching_tokens() {
+        let tokens = tokenize_spanned("aa bbb cc").unwrap();
+        assert_eq!(affected_token_range(&tokens, 2..3), 0..2);
+        assert_eq!(affected_token_range(&tokens, 6..6), 1..2);
+    }
+
+    #[test]
+    fn edit_between_tokens() {
+        let tokens = tokenize_spanned("aa   bbb").unwrap();
+        assert_eq!(affected_token_range(&tokens, 3..4), 1..1);
+    }
+
+    #[test]
+    fn nesting_depth() {
+        assert_eq!(max_nesting_depth(&tokenize("((a))").unwrap()), 2);
+        assert_eq!(max_nesting_depth(&tokenize("a + b").unwrap()), 0);
+    }
+
+    #[test]
+    fn top_level_statements() {
+        let tokens = tokenize("a;\nb; c").unwrap();
+        assert_eq!(statement_starts(&tokens), vec![Location { line: 0 }, Location { line: 1 }, Location { line: 1 }]);
+        let tokens = tokenize("f = { a; b };\ng;").unwrap();
+        assert_eq!(statement_starts(&tokens), vec![Location { line: 0 }, Location { line: 1 }]);
+        let config = LexerConfig { template_braces: true, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("{{a;\nb}};\nc", &config).unwrap();
+        assert_eq!(statement_starts(&tokens), vec![Location { line: 0 }, Location { line: 2 }]);
+    }
+
+    #[test]
+    fn adjacent_value_tokens() {
+        assert_eq!(adjacent_values(&tokenize("1 2").unwrap()), vec![Location { line: 0 }]);
+        assert_eq!(adjacent_values(&tokenize("1 + 2").unwrap()), Vec::<Location>::new());
+    }
+
+    #[test]
+    fn canonical_operators() {
+        let operator_aliases = HashMap::from([("and".to_string(), Token::AndAnd)]);
+        let config = LexerConfig { operator_aliases, ..LexerConfig::default() };
+        let mut tokens = tokenize_with_config("a and b && c", &config).unwrap();
+        let lexed = tokens.clone();
+        canonicalize_operators(&mut tokens);
+        assert_eq!(tokens, lexed);
+        let mut tokens = tokenize("a and b or c").unwrap();
+        canonicalize_operators(&mut tokens);
+        assert_eq!(tokens, tokenize("a && b || c").unwrap());
+    }
+
+    #[test]
+    fn control_char_literals() {
+        let tokens = tokenize("'a' '\\n' '\x07'\n'\u{1b}'").unwrap();
+        assert_eq!(nonprintable_char_literals(&tokens), vec![Location { line: 0 }, Location { line: 1 }]);
+        assert_eq!(nonprintable_char_literals(&tokenize("'a'").unwrap()), Vec::<Location>::new());
+    }
+
+    #[test]
+    fn duplicated_identifier() {
+        let tokens = tokenize("let foo\nfoo = 1").unwrap();
+        assert_eq!(duplicate_identifiers(&tokens), vec![Location { line: 1 }]);
+        assert_eq!(duplicate_identifiers(&tokenize("let foo = foo").unwrap()), Vec::<Location>::new());
+    }
+
+    #[test]
+    fn no_deprecated_tokens() {
+        let flag: fn(&[(Token, Location)]) -> Vec<Location> = flag_deprecated_tokens;
+        let tokens = tokenize("a ^ b + f(1.5, 'c')").unwrap();
+        assert_eq!(flag(&tokens), Vec::<Location>::new());
+    }
+
+    #[test]
+    fn token_left_of_offset() {
+        let tokens = tokenize_spanned("foo bar").unwrap();
+        assert_eq!(token_before(&tokens, 3), Some(&(Token::Identifier("foo".to_string()), Span { start: 0, end: 3 })));
+        assert_eq!(token_before(&tokens, 6), Some(&tokens[0]));
+        assert_eq!(token_before(&tokens, 2), None);
+    }
+
+    #[test]
+    fn generic_arguments() {
+        let tokens = tokenize("x: Map<K, Vec<T>> = a < b").unwrap();
+        assert!(is_likely_generic(&tokens, 3));
+        assert!(is_likely_generic(&tokens, 7));
+        assert!(!is_likely_generic(&tokens, 13));
+        assert!(!is_likely_generic(&tokenize("a < b && c > d").unwrap(), 1));
+        assert!(!is_likely_generic(&tokenize("1 < b > c").unwrap(), 1));
+    }
+
+    #[test]
+    fn definition_sites() {
+        let tokens = tokenize("let x = 1;\nfn f() {}").unwrap();
+        assert_eq!(definitions(&tokens), vec![("x", Location { line: 0 }), ("f", Location { line: 1 })]);
+    }
+
+    #[test]
+    fn edit_distance_over_kinds() {
+        let a = tokenize("f(x) = x + 1").unwrap();
+        let b = tokenize("g(y) =\ny + 2.5").unwrap();
+        assert_eq!(token_edit_distance(&a, &b), 0);
+        let c = tokenize("f(x) = -x + 1").unwrap();
+        assert_eq!(token_edit_distance(&a, &c), 1);
+        assert_eq!(token_edit_distance(&a, &[]), a.len());
+    }
+
+    #[test]
+    fn binding_or_annotation() {
+        let tokens = tokenize("x @ 1..10").unwrap();
+        assert!(is_binding_at(&tokens, 1));
+        let tokens = tokenize("@attr\nf(x) = x").unwrap();
+        assert!(!is_binding_at(&tokens, 0));
+        assert!(!is_binding_at(&tokens, 1));
+    }
+
+    #[test]
+    fn first_complete_statement() {
+        let tokens = tokenize("a; b;").unwrap();
+        assert_eq!(first_statement(&tokens), Some(&tokens[..2]));
+        let tokens = tokenize("f = { a; b").unwrap();
+        assert_eq!(first_statement(&tokens), None);
+        let config = LexerConfig { template_braces: true, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("{{a; b}}; c", &config).unwrap();
+        assert_eq!(first_statement(&tokens), Some(&tokens[..6]));
+    }
+
+    #[test]
+    fn expected_token_kinds() {
+        let tokens = tokenize("a * b").unwrap();
+        assert!(expect_one_of(&tokens, 0, &[TokenKind::Identifier]).is_ok());
+        let error = expect_one_of(&tokens, 1, &[TokenKind::Plus, TokenKind::Minus]).unwrap_err();
+        assert_eq!(error.to_string(), "Expected one of Plus, Minus, found Multiply on line 1");
+        let error = expect_one_of(&tokens, 3, &[TokenKind::Semi]).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::UnexpectedToken { found: None, expected: vec![TokenKind::Semi] });
+    }
+
+    #[test]
+    fn type_annotation_colons() {
+        let tokens = tokenize("let x: Int = {a: b}").unwrap();
+        assert!(is_type_annotation_colon(&tokens, 2));
+        assert!(!is_type_annotation_colon(&tokens, 7));
+        let tokens = tokenize("fn f(x: Int, y: Map<K, V>) = g(z: 1)").unwrap();
+        assert!(is_type_annotation_colon(&tokens, 4));
+        assert!(is_type_annotation_colon(&tokens, 8));
+        assert!(!is_type_annotation_colon(&tokens, 20));
+    }
+
+    #[test]
+    fn indentation_depths() {
+        let input = "fn f() = {\n    x\n}";
+        let tokens = tokenize(input).unwrap();
+        assert_eq!(indent_depths(input, &tokens), vec![0, 0, 0, 0, 0, 0, 4, 0]);
+    }
+
+    #[test]
+    fn reference_amps() {
+        let tokens = tokenize("&x; a & b; f(&y)").unwrap();
+        assert!(is_reference_amp(&tokens, 0));
+        assert!(!is_reference_amp(&tokens, 4));
+        assert!(is_reference_amp(&tokens, 9));
+        assert!(!is_reference_amp(&tokens, 1));
+    }
+}
@@ -0,0 +1,98 @@
+use crate::lexer::{LiteralKind, Token};
+
+/// Syntax highlighting category of a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HighlightGroup {
+    Operator,
+    Punctuation,
+    Identifier,
+    Keyword,
+    Number,
+    String,
+    Boolean,
+    Regex,
+    Directive,
+}
+
+pub fn highlight_group(token: &Token) -> HighlightGroup {
+    match token {
+        Token::Plus | Token::Minus | Token::Multiply | Token::Divide | Token::SlashSlash | Token::Carat
+        | Token::CaretCaret | Token::LessThan | Token::LessEq | Token::Spaceship | Token::Shl | Token::GreaterThan
+        | Token::And | Token::AndAnd | Token::Pipe | Token::OrOr | Token::Eq | Token::Arrow | Token::Question
+        | Token::QuestionDot | Token::Bang | Token::DotDot => HighlightGroup::Operator,
+        Token::LParen | Token::RParen | Token::Unit | Token::LBrace | Token::RBrace | Token::LBraceBrace
+        | Token::RBraceBrace | Token::LBracket | Token::RBracket | Token::HashBrace | Token::Hash | Token::At
+        | Token::Colon | Token::Comma | Token::Dot | Token::Semi => HighlightGroup::Punctuation,
+        Token::Identifier(_) | Token::Label(_) => HighlightGroup::Identifier,
+        Token::Keyword(_) => HighlightGroup::Keyword,
+        Token::Literal(literal) => match literal {
+            LiteralKind::Int { .. } | LiteralKind::Float { .. } | LiteralKind::Imaginary { .. }
+            | LiteralKind::Number { .. } => HighlightGroup::Number,
+            LiteralKind::Char { .. } | LiteralKind::RawChar { .. } | LiteralKind::String { .. } => HighlightGroup::String,
+            LiteralKind::Bool { .. } | LiteralKind::Null => HighlightGroup::Boolean,
+            LiteralKind::Regex { .. } => HighlightGroup::Regex,
+        },
+        Token::Directive(_) => HighlightGroup::Directive,
+    }
+}
+
+/// The token's `Display` output wrapped in an ANSI color for its highlight group
+#[cfg(feature = "ansi")]
+pub fn colorize(token: &Token) -> String {
+    let color = match highlight_group(token) {
+        HighlightGroup::Operator => "33",
+        HighlightGroup::Punctuation => "37",
+        HighlightGroup::Identifier => "39",
+        HighlightGroup::Keyword => "94",
+        HighlightGroup::Number => "36",
+        HighlightGroup::String => "32",
+        HighlightGroup::Boolean => "35",
+        HighlightGroup::Regex => "31",
+        HighlightGroup::Directive => "34",
+    };
+    format!("\x1b[{}m{}\x1b[0m", color, token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    #[test]
+    fn highlight_groups() {
+        let tokens = tokenize("a + 1 \"s\" true;").unwrap();
+        let groups: Vec<HighlightGroup> = tokens.iter().map(|(token, _)| highlight_group(token)).collect();
+        assert_eq!(groups, vec![
+            HighlightGroup::Identifier,
+            HighlightGroup::Operator,
+            HighlightGroup::Number,
+            HighlightGroup::String,
+            HighlightGroup::Boolean,
+            HighlightGroup::Punctuation
+        ]);
+    }
+
+    #[cfg(feature = "ansi")]
+    fn strip_ansi(text: &str) -> String {
+        let mut result = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                result.push(c);
+            }
+        }
+        result
+    }
+
+    #[cfg(feature = "ansi")]
+    #[test]
+    fn colorize_keeps_display_text() {
+        for (token, _) in tokenize("f(x) = 'c' + 1.5 -> \"s\"").unwrap() {
+            let colored = colorize(&token);
+            assert!(colored.starts_with("\x1b["));
+            assert_eq!(strip_ansi(&colored), token.to_string());
+        }
+    }
+}
\ No newline at end of file
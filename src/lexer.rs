@@ -1,47 +1,482 @@
 #[allow(dead_code)]
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::iter::Peekable;
+use std::ops::Range;
+use std::rc::Rc;
 use std::str::Chars;
+use crate::lint::{Diagnostic, Severity};
+use crate::source::Source;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LiteralKind {
-    Int { value: i64 },     // 123
+    Int { value: i64, radix: Radix, raw: String }, // 123, 0xFF with radix prefixes
     Float { value: f64 }, // 123.456
     Char { value: char }, // 'a'
     Bool { value: bool }, // true or false
-    String { value: String } // "hello world"
+    String { value: String }, // "hello world"
+    Regex { pattern: String, flags: String }, // /ab+c/i
+    Null, // the configured null keyword, e.g. none
+    Imaginary { value: f64 }, // 3i
+    Number { raw: String }, // 1.5e3, left unparsed in raw mode
+    RawChar { raw: String }, // '\n', a char literal that isn't one character in raw mode
 }
 
-#[derive(Debug, PartialEq)]
+/// Base an integer literal was written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Radix {
+    Binary,
+    Octal,
+    Decimal,
+    Hex,
+    Other(u32), // any other base from 3 to 36, written like 3r120
+}
+
+impl Radix {
+    pub fn base(self) -> u32 {
+        match self {
+            Radix::Binary => 2,
+            Radix::Octal => 8,
+            Radix::Decimal => 10,
+            Radix::Hex => 16,
+            Radix::Other(base) => base,
+        }
+    }
+
+    pub fn from_base(base: u32) -> Radix {
+        match base {
+            2 => Radix::Binary,
+            8 => Radix::Octal,
+            10 => Radix::Decimal,
+            16 => Radix::Hex,
+            base => Radix::Other(base),
+        }
+    }
+}
+
+impl LiteralKind {
+    /// A decimal integer literal, as if `value` had been written in source
+    pub fn int(value: i64) -> LiteralKind {
+        LiteralKind::Int { value, radix: Radix::Decimal, raw: value.to_string() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Plus, // +
     Minus, // -
     Multiply, // *
     Divide, // /
+    SlashSlash, // //, integer division with `integer_division`
     Carat, // ^
+    CaretCaret, // ^^
     LessThan, // <
+    LessEq, // <=
+    Spaceship, // <=>
+    Shl, // <<
     GreaterThan, // >
     And, // &
+    AndAnd, // &&
+    Pipe, // |
+    OrOr, // ||
     Eq, // =
     LParen, // (
     RParen, // )
+    Unit, // (), only with `collapse_unit`
     LBrace, // {
     RBrace, // }
+    LBraceBrace, // {{, opening a template interpolation with `template_braces`
+    RBraceBrace, // }}
+    LBracket, // [
+    RBracket, // ]
+    HashBrace, // #{, opening a set or map literal closed by `}`
+    Hash, // #
+    At, // @, starting an annotation like @route("/path")
     Colon, // :
-    Identifier(String), // keywords are also considered identifiers
+    Comma, // ,
+    Dot, // .
+    DotDot, // .., as in the range `'a'..'z'`
+    Question, // ?
+    QuestionDot, // ?.
+    Bang, // !, postfix with `postfix_bang`
+    Identifier(String), // keywords other than `Keyword`s are also considered identifiers
     Literal(LiteralKind),
     Semi, // ;
     Arrow, // ->
+    Directive(String), // !pragma once, with the directive prefix configured as "pragma "
+    Label(String), // 'outer
+    Keyword(String), // one of `KEYWORDS`, e.g. let
 }
 
-#[derive(Debug, PartialEq)]
+/// The variant of a `Token` without its payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    SlashSlash,
+    Carat,
+    CaretCaret,
+    LessThan,
+    LessEq,
+    Spaceship,
+    Shl,
+    GreaterThan,
+    And,
+    AndAnd,
+    Pipe,
+    OrOr,
+    Eq,
+    LParen,
+    RParen,
+    Unit,
+    LBrace,
+    RBrace,
+    LBraceBrace,
+    RBraceBrace,
+    LBracket,
+    RBracket,
+    HashBrace,
+    Hash,
+    At,
+    Colon,
+    Comma,
+    Dot,
+    DotDot,
+    Question,
+    QuestionDot,
+    Bang,
+    Identifier,
+    Literal,
+    Semi,
+    Arrow,
+    Directive,
+    Label,
+    Keyword,
+}
+
+impl Token {
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Token::Plus => TokenKind::Plus,
+            Token::Minus => TokenKind::Minus,
+            Token::Multiply => TokenKind::Multiply,
+            Token::Divide => TokenKind::Divide,
+            Token::SlashSlash => TokenKind::SlashSlash,
+            Token::Carat => TokenKind::Carat,
+            Token::CaretCaret => TokenKind::CaretCaret,
+            Token::LessThan => TokenKind::LessThan,
+            Token::LessEq => TokenKind::LessEq,
+            Token::Spaceship => TokenKind::Spaceship,
+            Token::Shl => TokenKind::Shl,
+            Token::GreaterThan => TokenKind::GreaterThan,
+            Token::And => TokenKind::And,
+            Token::AndAnd => TokenKind::AndAnd,
+            Token::Pipe => TokenKind::Pipe,
+            Token::OrOr => TokenKind::OrOr,
+            Token::Eq => TokenKind::Eq,
+            Token::LParen => TokenKind::LParen,
+            Token::RParen => TokenKind::RParen,
+            Token::Unit => TokenKind::Unit,
+            Token::LBrace => TokenKind::LBrace,
+            Token::RBrace => TokenKind::RBrace,
+            Token::LBraceBrace => TokenKind::LBraceBrace,
+            Token::RBraceBrace => TokenKind::RBraceBrace,
+            Token::LBracket => TokenKind::LBracket,
+            Token::RBracket => TokenKind::RBracket,
+            Token::HashBrace => TokenKind::HashBrace,
+            Token::Hash => TokenKind::Hash,
+            Token::At => TokenKind::At,
+            Token::Colon => TokenKind::Colon,
+            Token::Comma => TokenKind::Comma,
+            Token::Dot => TokenKind::Dot,
+            Token::DotDot => TokenKind::DotDot,
+            Token::Question => TokenKind::Question,
+            Token::QuestionDot => TokenKind::QuestionDot,
+            Token::Bang => TokenKind::Bang,
+            Token::Identifier(_) => TokenKind::Identifier,
+            Token::Literal(_) => TokenKind::Literal,
+            Token::Semi => TokenKind::Semi,
+            Token::Arrow => TokenKind::Arrow,
+            Token::Directive(_) => TokenKind::Directive,
+            Token::Label(_) => TokenKind::Label,
+            Token::Keyword(_) => TokenKind::Keyword,
+        }
+    }
+
+    /// Whether the token ends an operand, i.e. a `/` following it is a division rather than the
+    /// start of a regex literal
+    pub fn is_value(&self) -> bool {
+        matches!(self, Token::Identifier(_) | Token::Literal(_) | Token::RParen | Token::RBracket | Token::Unit)
+    }
+}
+
+/// Writes the literal the way it's spelled in source, except that `Null` is always `null`
+/// whatever the `null_keyword`
+impl fmt::Display for LiteralKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LiteralKind::Int { raw, .. } => write!(f, "{}", raw),
+            LiteralKind::Float { value } if value.fract() == 0.0 => write!(f, "{}.0", value),
+            LiteralKind::Float { value } => write!(f, "{}", value),
+            LiteralKind::Char { value } => write!(f, "{:?}", value),
+            LiteralKind::Bool { value } => write!(f, "{}", value),
+            LiteralKind::String { value } => write!(f, "{:?}", value),
+            LiteralKind::Regex { pattern, flags } => write!(f, "/{}/{}", pattern, flags),
+            LiteralKind::Null => write!(f, "null"),
+            LiteralKind::Imaginary { value } => write!(f, "{}i", value),
+            LiteralKind::Number { raw } => write!(f, "{}", raw),
+            LiteralKind::RawChar { raw } => write!(f, "'{}'", raw),
+        }
+    }
+}
+
+/// Writes the token the way it's spelled in source. Identifiers spelled like a keyword or one of
+/// the default bool literals get an `r#` prefix, so they lex back as identifiers. Directives
+/// and null literals lose the configured `directive_prefix` and `null_keyword`, and are
+/// written as `!` and the directive text, and as `null`
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Multiply => "*",
+            Token::Divide => "/",
+            Token::SlashSlash => "//",
+            Token::Carat => "^",
+            Token::CaretCaret => "^^",
+            Token::LessThan => "<",
+            Token::LessEq => "<=",
+            Token::Spaceship => "<=>",
+            Token::Shl => "<<",
+            Token::GreaterThan => ">",
+            Token::And => "&",
+            Token::AndAnd => "&&",
+            Token::Pipe => "|",
+            Token::OrOr => "||",
+            Token::Eq => "=",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::Unit => "()",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBraceBrace => "{{",
+            Token::RBraceBrace => "}}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::HashBrace => "#{",
+            Token::Hash => "#",
+            Token::At => "@",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::DotDot => "..",
+            Token::Question => "?",
+            Token::QuestionDot => "?.",
+            Token::Bang => "!",
+            Token::Semi => ";",
+            Token::Arrow => "->",
+            Token::Identifier(name) if KEYWORDS.contains(&name.as_str()) || name == "true" || name == "false" => {
+                return write!(f, "r#{}", name);
+            }
+            Token::Identifier(name) | Token::Keyword(name) => name,
+            Token::Literal(literal) => return write!(f, "{}", literal),
+            Token::Directive(directive) => return write!(f, "!{}", directive),
+            Token::Label(label) => return write!(f, "'{}", label),
+        };
+        f.write_str(text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Location {
     pub line: usize
 }
 
-/// Consume characters from a Peekable<Chars> iterator while a condition Fn(char) -> bool is true
+/// Byte range of a token in the source, `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
+}
+
+/// Identifier of a token, assigned in source order by `tokenize_with_ids`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TokenId(pub u64);
+
+#[derive(Debug, PartialEq)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    InvalidEscape(String), // the text after the backslash
+    SurrogateEscape(u32),
+    InvalidNumber(String),
+    InvalidCharLiteral(String),
+    UnterminatedString,
+    UnterminatedChar,
+    NewlineInString,
+    StringTooLong(usize), // the configured limit
+    LeadingZero(String),
+    DisallowedToken(TokenKind),
+    UnclosedDelimiter(TokenKind), // the opening delimiter
+    UnexpectedClosingDelimiter(TokenKind),
+    UnexpectedToken { found: Option<TokenKind>, expected: Vec<TokenKind> }, // found is None at the end of input
+    InvalidRange(Range<usize>), // a byte range outside the input or not on char boundaries
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub location: Location
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexErrorKind::UnexpectedCharacter(c) => write!(f, "Unexpected character: {}", c),
+            LexErrorKind::InvalidEscape(escape) => write!(f, "Invalid escape sequence: \\{}", escape),
+            LexErrorKind::SurrogateEscape(code) => write!(f, "Invalid escape: \\u{{{:X}}} is a surrogate code point, not a char", code),
+            LexErrorKind::InvalidNumber(number) => write!(f, "Invalid number literal: {}", number),
+            LexErrorKind::InvalidCharLiteral(character) => write!(f, "Invalid character literal: {}", character),
+            LexErrorKind::UnterminatedString => write!(f, "Unterminated string"),
+            LexErrorKind::UnterminatedChar => write!(f, "Unterminated character literal"),
+            LexErrorKind::UnclosedDelimiter(kind) => write!(f, "Unclosed delimiter: {:?}", kind),
+            LexErrorKind::UnexpectedClosingDelimiter(kind) => write!(f, "Unexpected closing delimiter: {:?}", kind),
+            LexErrorKind::DisallowedToken(kind) => write!(f, "Token not allowed here: {:?}", kind),
+            LexErrorKind::UnexpectedToken { found, expected } => {
+                let expected: Vec<String> = expected.iter().map(|kind| format!("{:?}", kind)).collect();
+                match found {
+                    Some(found) => write!(f, "Expected one of {}, found {:?}", expected.join(", "), found),
+                    None => write!(f, "Expected one of {}, found the end of input", expected.join(", ")),
+                }
+            }
+            LexErrorKind::LeadingZero(number) => write!(f, "Integer literal with a leading zero: {}", number),
+            LexErrorKind::StringTooLong(limit) => write!(f, "String literal is longer than {} characters", limit),
+            LexErrorKind::NewlineInString => write!(f, "Newline in string literal, use \\n instead"),
+            LexErrorKind::InvalidRange(range) => write!(f, "Byte range {:?} is outside the input or splits a character", range),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} on line {}", self.kind, self.location.line + 1)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// How an integer literal with a leading zero, like `0123`, is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeadingZeroPolicy {
+    Decimal,
+    Octal,
+    Error,
+}
+
+/// How a char literal holding more than one character, like `'ab'`, is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharPolicy {
+    Strict,
+    FirstChar,
+}
+
+/// Options that change how the source is tokenized. `LexerConfig::default()` gives the standard
+/// Slo syntax
+#[derive(Debug)]
+pub struct LexerConfig {
+    /// Decimal point used in float literals, e.g. `,` for `3,14`
+    pub decimal_separator: char,
+    /// Sentinel character that ends lexing as if the input stopped there, e.g. `\x04` (EOT)
+    pub eof_marker: Option<char>,
+    /// Words lexed as operator tokens instead of identifiers, e.g. `and` as `Token::AndAnd`
+    pub operator_aliases: HashMap<String, Token>,
+    /// Comments starting with this prefix become `Token::Directive` instead of being discarded.
+    /// The prefix isn't kept, so a directive displays as `!` and the text after the prefix
+    pub directive_prefix: Option<String>,
+    /// Markers that start a comment running to the end of the line, e.g. `!` and `--`. The
+    /// longest matching marker wins
+    pub line_comment_tokens: Vec<String>,
+    /// Apply Unicode NFC normalization to identifiers, so composed and decomposed spellings of
+    /// the same name produce the same token
+    pub normalize_identifiers_nfc: bool,
+    /// Allow a raw newline inside a string literal, otherwise it has to be written as `\n`
+    pub allow_multiline_strings: bool,
+    /// Accept `,` between groups of three digits in number literals, e.g. `1,000`. Ignored when
+    /// `,` is the decimal separator
+    pub comma_thousands: bool,
+    /// Longest string literal allowed, in chars after escapes are decoded
+    pub max_string_len: Option<usize>,
+    /// Lex `(` directly followed by `)`, with nothing in between, as a single `Token::Unit`
+    pub collapse_unit: bool,
+    /// How to read integers with a leading zero such as `0123`
+    pub leading_zero: LeadingZeroPolicy,
+    /// Accept `0x`, `0o` and `0b` prefixed integers
+    pub radix_prefixes: bool,
+    /// Keyword lexed as `LiteralKind::Null`, e.g. `none`, `nil` or `null`. The literal doesn't
+    /// keep the keyword, and always displays as `null`
+    pub null_keyword: Option<String>,
+    /// Words lexed as `LiteralKind::Bool` and their values, `true` and `false` by default
+    pub bool_literals: HashMap<String, bool>,
+    /// Accept integers in any base from 2 to 36 written as `<radix>r<digits>`, e.g. `16rFF`
+    pub arbitrary_radix: bool,
+    /// Split the input into tokens without interpreting them: string escapes are kept as written,
+    /// words are never keywords or literals, and numbers become `LiteralKind::Number`. Char
+    /// literals keep their escapes too, and those that aren't one character become
+    /// `LiteralKind::RawChar`
+    pub raw_mode: bool,
+    /// Read `""` inside a string literal as one `"` instead of the end of the string, e.g.
+    /// `"say ""hi"""` is `say "hi"`
+    pub raw_string_double_escape: bool,
+    /// Lex `//` as `Token::SlashSlash`, for integer division. Has no effect if `//` is one of the
+    /// `line_comment_tokens`, since comments are recognized first
+    pub integer_division: bool,
+    /// Lex a `!` right after a value, with no space in between, as `Token::Bang` instead of a
+    /// comment, e.g. the factorial in `5!`
+    pub postfix_bang: bool,
+    /// Lex `{{` and `}}` as `Token::LBraceBrace` and `Token::RBraceBrace`, for template
+    /// interpolation like `{{ name }}`. Nested blocks then need a space between their braces
+    pub template_braces: bool,
+    /// Whether char literals must hold exactly one character, or keep the first one and warn
+    pub char_literal: CharPolicy,
+}
+
+impl Default for LexerConfig {
+    fn default() -> Self {
+        LexerConfig {
+            decimal_separator: '.',
+            eof_marker: None,
+            operator_aliases: HashMap::new(),
+            directive_prefix: None,
+            line_comment_tokens: vec!["!".to_string()],
+            normalize_identifiers_nfc: false,
+            allow_multiline_strings: true,
+            comma_thousands: false,
+            max_string_len: None,
+            collapse_unit: false,
+            leading_zero: LeadingZeroPolicy::Decimal,
+            radix_prefixes: false,
+            null_keyword: None,
+            bool_literals: HashMap::from([("true".to_string(), true), ("false".to_string(), false)]),
+            arbitrary_radix: false,
+            raw_mode: false,
+            raw_string_double_escape: false,
+            integer_division: false,
+            postfix_bang: false,
+            template_braces: false,
+            char_literal: CharPolicy::Strict,
+        }
+    }
+}
+
+/// Consume characters from a Peekable<Iterator<Item = char>> while a condition Fn(char) -> bool is true
 /// and return the consumed characters as a String
-pub fn consume_while<F>(chars: &mut Peekable<Chars>, condition: F) -> String
+pub fn consume_while<I, F>(chars: &mut Peekable<I>, condition: F) -> String
 where
+    I: Iterator<Item = char>,
     F: Fn(char) -> bool,
 {
     let mut result = String::new();
@@ -56,46 +491,348 @@ where
     result
 }
 
-pub fn tokenize_number(chars: &mut Peekable<Chars>) -> Token {
-    let number = consume_while(chars, |c| c.is_ascii_digit() || c == '.');
-    if number.contains('.') {
-        Token::Literal(LiteralKind::Float { value: number.parse().unwrap() })
+/// Whether the next char is `c` and the one after it is a digit
+fn separator_before_digit<I: Iterator<Item = char> + Clone>(chars: &Peekable<I>, c: char) -> bool {
+    let mut lookahead = chars.clone();
+    lookahead.next() == Some(c) && lookahead.peek().is_some_and(char::is_ascii_digit)
+}
+
+/// Read an integer with a `0x`, `0o` or `0b` prefix, which `chars` is known to start with
+fn tokenize_prefixed_int<I: Iterator<Item = char>>(chars: &mut Peekable<I>, radix: Radix) -> Result<Token, LexErrorKind> {
+    let mut raw: String = chars.take(2).collect();
+    let digits = consume_while(chars, |c| c.is_ascii_alphanumeric());
+    raw.push_str(&digits);
+    match i64::from_str_radix(&digits, radix.base()) {
+        Ok(value) => Ok(Token::Literal(LiteralKind::Int { value, radix, raw })),
+        _ => Err(LexErrorKind::InvalidNumber(raw)),
+    }
+}
+
+/// Read the digits of a `<radix>r<digits>` integer whose radix has already been consumed, if
+/// `chars` is on the `r`. Every digit must be valid in the radix
+fn tokenize_radix_int<I: Iterator<Item = char> + Clone>(chars: &mut Peekable<I>, radix: &str) -> Option<Result<Token, LexErrorKind>> {
+    let mut lookahead = chars.clone();
+    if lookahead.next() != Some('r') || !lookahead.peek().is_some_and(char::is_ascii_alphanumeric) {
+        return None;
+    }
+    chars.next();
+    let digits = consume_while(chars, |c| c.is_ascii_alphanumeric());
+    let raw = format!("{}r{}", radix, digits);
+    let base = radix.parse().ok().filter(|base| (2..=36).contains(base));
+    let value = base.and_then(|base| i64::from_str_radix(&digits, base).ok());
+    Some(match (base, value) {
+        (Some(base), Some(value)) => Ok(Token::Literal(LiteralKind::Int { value, radix: Radix::from_base(base), raw })),
+        _ => Err(LexErrorKind::InvalidNumber(raw)),
+    })
+}
+
+/// Turn a number directly followed by a lone `i`, like `3i`, into an imaginary literal. An `i`
+/// that starts a longer identifier, as in `3item`, is left alone
+fn imaginary_suffix<I: Iterator<Item = char> + Clone>(chars: &mut Peekable<I>, value: f64) -> Option<Token> {
+    let mut lookahead = chars.clone();
+    if lookahead.next() != Some('i') || lookahead.peek().is_some_and(|&c| c.is_alphanumeric() || c == '_' || c == '\'') {
+        return None;
+    }
+    chars.next();
+    Some(Token::Literal(LiteralKind::Imaginary { value }))
+}
+
+/// Tokenize an integer or float. The fractional part is only taken when the decimal separator
+/// is followed by a digit, so `1.x` and, with a `,` separator, `f(1, 2)` still lex sensibly.
+/// With `comma_thousands`, digits may be grouped in threes like `1,000,000`
+pub fn tokenize_number<I: Iterator<Item = char> + Clone>(chars: &mut Peekable<I>, config: &LexerConfig) -> Result<Token, LexErrorKind> {
+    if config.raw_mode {
+        let mut raw = consume_while(chars, |c| c.is_ascii_alphanumeric() || c == '_');
+        while separator_before_digit(chars, config.decimal_separator) {
+            raw.extend(chars.next());
+            raw.push_str(&consume_while(chars, |c| c.is_ascii_alphanumeric() || c == '_'));
+        }
+        return Ok(Token::Literal(LiteralKind::Number { raw }));
+    }
+    if config.radix_prefixes {
+        let mut lookahead = chars.clone();
+        if lookahead.next() == Some('0') {
+            let radix = match lookahead.next() {
+                Some('x' | 'X') => Some(Radix::Hex),
+                Some('o' | 'O') => Some(Radix::Octal),
+                Some('b' | 'B') => Some(Radix::Binary),
+                _ => None,
+            };
+            if let Some(radix) = radix {
+                return tokenize_prefixed_int(chars, radix);
+            }
+        }
+    }
+    let mut number = consume_while(chars, |c| c.is_ascii_digit());
+    if config.arbitrary_radix {
+        if let Some(result) = tokenize_radix_int(chars, &number) {
+            return result;
+        }
+    }
+    let mut raw = number.clone();
+    if config.comma_thousands && config.decimal_separator != ',' && separator_before_digit(chars, ',') {
+        let mut valid = number.len() <= 3;
+        while separator_before_digit(chars, ',') {
+            chars.next();
+            let group = consume_while(chars, |c| c.is_ascii_digit());
+            valid &= group.len() == 3;
+            raw.push(',');
+            raw.push_str(&group);
+            number.push_str(&group);
+        }
+        if !valid {
+            return Err(LexErrorKind::InvalidNumber(raw));
+        }
+    }
+    if separator_before_digit(chars, config.decimal_separator) {
+        chars.next();
+        number.push('.');
+        number.push_str(&consume_while(chars, |c| c.is_ascii_digit()));
+        let value = number.parse().map_err(|_| LexErrorKind::InvalidNumber(number))?;
+        Ok(imaginary_suffix(chars, value).unwrap_or(Token::Literal(LiteralKind::Float { value })))
     } else {
-        Token::Literal(LiteralKind::Int { value: number.parse().unwrap() })
+        let radix = if number.len() > 1 && number.starts_with('0') {
+            match config.leading_zero {
+                LeadingZeroPolicy::Decimal => Radix::Decimal,
+                LeadingZeroPolicy::Octal => Radix::Octal,
+                LeadingZeroPolicy::Error => return Err(LexErrorKind::LeadingZero(number)),
+            }
+        } else {
+            Radix::Decimal
+        };
+        let value = i64::from_str_radix(&number, radix.base()).map_err(|_| LexErrorKind::InvalidNumber(raw.clone()))?;
+        Ok(imaginary_suffix(chars, value as f64).unwrap_or(Token::Literal(LiteralKind::Int { value, radix, raw })))
+    }
+}
+
+/// Decode an escape sequence, the backslash has already been consumed. Supports `\n`, `\t`, `\r`,
+/// `\0`, `\\`, `\"`, `\'`, `\xNN` (ASCII only) and `\u{NNNN}`
+pub fn tokenize_escape<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Result<char, LexErrorKind> {
+    let c = chars.next().ok_or_else(|| LexErrorKind::InvalidEscape(String::new()))?;
+    match c {
+        'n' => Ok('\n'),
+        't' => Ok('\t'),
+        'r' => Ok('\r'),
+        '0' => Ok('\0'),
+        '\\' | '"' | '\'' => Ok(c),
+        'x' => {
+            let digits: String = chars.take(2).collect();
+            match u8::from_str_radix(&digits, 16) {
+                Ok(byte) if digits.len() == 2 && byte.is_ascii() => Ok(byte as char),
+                _ => Err(LexErrorKind::InvalidEscape(format!("x{}", digits))),
+            }
+        }
+        'u' => {
+            if chars.next() != Some('{') {
+                return Err(LexErrorKind::InvalidEscape("u".to_string()));
+            }
+            let digits = consume_while(chars, |c| c.is_ascii_hexdigit());
+            let invalid = || LexErrorKind::InvalidEscape(format!("u{{{}", digits));
+            if chars.next() != Some('}') || digits.is_empty() || digits.len() > 6 {
+                return Err(invalid());
+            }
+            let code = u32::from_str_radix(&digits, 16).map_err(|_| invalid())?;
+            if (0xD800..=0xDFFF).contains(&code) {
+                return Err(LexErrorKind::SurrogateEscape(code));
+            }
+            char::from_u32(code).ok_or_else(invalid)
+        }
+        _ => Err(LexErrorKind::InvalidEscape(c.to_string())),
     }
 }
 
-pub fn tokenize_string(chars: &mut Peekable<Chars>) -> Token {
+/// Whether `chars` starts with `quote` twice
+fn is_doubled<I: Iterator<Item = char> + Clone>(chars: &Peekable<I>, quote: char) -> bool {
+    let mut lookahead = chars.clone();
+    lookahead.next() == Some(quote) && lookahead.peek() == Some(&quote)
+}
+
+/// Consume a quoted literal up to the closing `quote`, decoding escapes along the way. Fails as
+/// soon as the decoded text gets longer than `max_len` chars
+fn consume_quoted<I: Iterator<Item = char> + Clone>(
+    chars: &mut Peekable<I>,
+    quote: char,
+    allow_newline: bool,
+    max_len: Option<usize>,
+    keep_escapes: bool,
+    doubled_quote: bool,
+) -> Result<String, LexErrorKind> {
     chars.next();
-    let string = consume_while(chars, |c| c != '"');
+    let mut result = String::new();
+    let mut len = 0;
+    while let Some(&c) = chars.peek() {
+        if c == quote && !(doubled_quote && is_doubled(chars, quote)) {
+            break;
+        }
+        if c == quote {
+            chars.next();
+        }
+        if c == '\n' && !allow_newline {
+            return Err(LexErrorKind::NewlineInString);
+        }
+        chars.next();
+        if c == '\\' && keep_escapes {
+            result.push(c);
+            result.extend(chars.next());
+        } else if c == '\\' {
+            result.push(tokenize_escape(chars)?);
+        } else {
+            result.push(c);
+        }
+        len += 1;
+        if let Some(max_len) = max_len.filter(|&max_len| len > max_len) {
+            return Err(LexErrorKind::StringTooLong(max_len));
+        }
+    }
+    Ok(result)
+}
+
+pub fn tokenize_string<I: Iterator<Item = char> + Clone>(chars: &mut Peekable<I>, config: &LexerConfig) -> Result<Token, LexErrorKind> {
+    let string = consume_quoted(chars, '"', config.allow_multiline_strings, config.max_string_len, config.raw_mode, config.raw_string_double_escape)?;
     if chars.peek() != Some(&'"') {
-        panic!("Unterminated string");
+        return Err(LexErrorKind::UnterminatedString);
+    }
+    chars.next();
+    Ok(Token::Literal(LiteralKind::String { value: string }))
+}
+
+/// Read a `'label`, i.e. a `'` followed by an identifier that isn't closed by another `'` like a
+/// char literal would be. Nothing is consumed if it isn't a label
+pub fn tokenize_label<I: Iterator<Item = char> + Clone>(chars: &mut Peekable<I>) -> Option<Token> {
+    let mut lookahead = chars.clone();
+    lookahead.next();
+    if !lookahead.peek().is_some_and(|&c| c.is_alphabetic() || c == '_') {
+        return None;
+    }
+    let label = consume_while(&mut lookahead, |c| c.is_alphabetic() || c == '_' || c.is_ascii_digit());
+    if lookahead.peek() == Some(&'\'') {
+        return None;
+    }
+    for _ in 0..label.chars().count() + 1 {
+        chars.next();
+    }
+    Some(Token::Label(label))
+}
+
+/// Lex a char literal. Under `CharPolicy::FirstChar` a literal with extra characters is truncated
+/// to its first one, and its full contents are returned alongside so the caller can warn. In raw
+/// mode escapes are kept as written, and a literal that isn't one character is a `RawChar`
+pub fn tokenize_char<I: Iterator<Item = char> + Clone>(chars: &mut Peekable<I>, config: &LexerConfig) -> Result<(Token, Option<String>), LexErrorKind> {
+    let character = consume_quoted(chars, '\'', true, None, config.raw_mode, false)?;
+    if chars.peek() != Some(&'\'') {
+        return Err(LexErrorKind::UnterminatedChar);
+    }
+    chars.next();
+    let mut iter = character.chars();
+    match (iter.next(), iter.next(), config.char_literal) {
+        (Some(value), None, _) => Ok((Token::Literal(LiteralKind::Char { value }), None)),
+        _ if config.raw_mode => Ok((Token::Literal(LiteralKind::RawChar { raw: character }), None)),
+        (Some(value), Some(_), CharPolicy::FirstChar) => Ok((Token::Literal(LiteralKind::Char { value }), Some(character))),
+        _ => Err(LexErrorKind::InvalidCharLiteral(character)),
+    }
+}
+
+/// Words lexed as `Token::Keyword`, all other keywords are plain identifiers
+pub const KEYWORDS: &[&str] = &["fn", "let", "when", "where"];
+
+/// Whether `chars` is positioned on the `#` of an `r#word` raw identifier
+fn raw_identifier_prefix<I: Iterator<Item = char> + Clone>(chars: &Peekable<I>) -> bool {
+    let mut lookahead = chars.clone();
+    lookahead.next() == Some('#') && lookahead.peek().is_some_and(char::is_ascii_alphabetic)
+}
+
+pub fn tokenize_identifier<I: Iterator<Item = char> + Clone>(chars: &mut Peekable<I>, config: &LexerConfig) -> Token {
+    let condition = |c: char| c.is_alphabetic() || c == '_' || c.is_ascii_digit() || c == '\'' || is_combining_mark(c);
+    let mut identifier = consume_while(chars, condition);
+    // `r#word` always lexes as an identifier, bypassing keywords and aliases
+    let raw = identifier == "r" && raw_identifier_prefix(chars);
+    if raw {
+        chars.next();
+        identifier = consume_while(chars, condition);
+    }
+    if config.normalize_identifiers_nfc {
+        identifier = identifier.nfc().collect();
     }
+    if raw || config.raw_mode {
+        return Token::Identifier(identifier);
+    }
+    if let Some(&value) = config.bool_literals.get(&identifier) {
+        return Token::Literal(LiteralKind::Bool { value });
+    }
+    if config.null_keyword.as_deref() == Some(identifier.as_str()) {
+        return Token::Literal(LiteralKind::Null);
+    }
+    if KEYWORDS.contains(&identifier.as_str()) {
+        return Token::Keyword(identifier);
+    }
+    match config.operator_aliases.get(&identifier) {
+        Some(token) => token.clone(),
+        None => Token::Identifier(identifier),
+    }
+}
+
+/// A `{` or `}`, or with `template_braces` a doubled one
+pub fn tokenize_brace<I: Iterator<Item = char>>(chars: &mut Peekable<I>, config: &LexerConfig) -> Token {
+    let open = chars.next() == Some('{');
+    let brace = if open { '{' } else { '}' };
+    if config.template_braces && chars.peek() == Some(&brace) {
+        chars.next();
+        return if open { Token::LBraceBrace } else { Token::RBraceBrace };
+    }
+    if open { Token::LBrace } else { Token::RBrace }
+}
+
+pub fn tokenize_hash<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
     chars.next();
-    Token::Literal(LiteralKind::String { value: string })
+    if let Some(&'{') = chars.peek() {
+        chars.next();
+        Token::HashBrace
+    } else {
+        Token::Hash
+    }
 }
 
-pub fn tokenize_char(chars: &mut Peekable<Chars>) -> Token {
+pub fn tokenize_dot<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
     chars.next();
-    let character = consume_while(chars, |c| c != '\'');
-    if character.len() != 1 {
-        panic!("Invalid character literal: {}", character);
+    if let Some(&'.') = chars.peek() {
+        chars.next();
+        Token::DotDot
+    } else {
+        Token::Dot
     }
+}
+
+pub fn tokenize_question<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
     chars.next();
-    Token::Literal(LiteralKind::Char { value: character.chars().next().unwrap() })
+    if let Some(&'.') = chars.peek() {
+        chars.next();
+        Token::QuestionDot
+    } else {
+        Token::Question
+    }
 }
 
-pub fn tokenize_identifier(chars: &mut Peekable<Chars>) -> Token {
-    let condition = |c: char| c.is_alphabetic() || c == '_' || c.is_ascii_digit() || c == '\'';
-    let identifier = consume_while(chars, condition);
-    match identifier.as_str() {
-        "true" => Token::Literal(LiteralKind::Bool { value: true }),
-        "false" => Token::Literal(LiteralKind::Bool { value: false }),
-        _ => Token::Identifier(identifier),
+pub fn tokenize_less<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
+    chars.next();
+    match chars.peek() {
+        Some(&'=') => {
+            chars.next();
+            if let Some(&'>') = chars.peek() {
+                chars.next();
+                Token::Spaceship
+            } else {
+                Token::LessEq
+            }
+        }
+        Some(&'<') => {
+            chars.next();
+            Token::Shl
+        }
+        _ => Token::LessThan,
     }
 }
 
-pub fn tokenize_minus(chars: &mut Peekable<Chars>) -> Token {
+pub fn tokenize_minus<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
     chars.next();
     if let Some(&'>') = chars.peek() {
         chars.next();
@@ -105,132 +842,1364 @@ pub fn tokenize_minus(chars: &mut Peekable<Chars>) -> Token {
     }
 }
 
-/// Tokenize the input string and return a vector of tokens with their locations
-pub fn tokenize<T: AsRef<str>>(input: T) -> Result<Vec<(Token, Location)>, (String, Location)> {
-    let mut tokens: Vec<(Token, Location)> = Vec::new();
-    let mut chars = input.as_ref().chars().peekable();
-    let mut line = 0;
-    while let Some(&c) = chars.peek() {
-        let token = match c {
-            '0'..='9' => tokenize_number(&mut chars),
-            '"' => tokenize_string(&mut chars),
-            '\'' => tokenize_char(&mut chars),
-            'a'..='z' | 'A'..='Z' => tokenize_identifier(&mut chars),
-            '+' => { chars.next(); Token::Plus },
-            '-' => tokenize_minus(&mut chars),
-            '*' => { chars.next(); Token::Multiply },
-            '/' => { chars.next(); Token::Divide },
-            '^' => { chars.next(); Token::Carat },
-            '(' => { chars.next(); Token::LParen },
-            ')' => { chars.next(); Token::RParen },
-            '{' => { chars.next(); Token::LBrace },
-            '}' => { chars.next(); Token::RBrace },
-            ':' => { chars.next(); Token::Colon },
-            ';' => { chars.next(); Token::Semi },
-            '<' => { chars.next(); Token::LessThan },
-            '>' => { chars.next(); Token::GreaterThan },
-            '&' => { chars.next(); Token::And },
-            '=' => { chars.next(); Token::Eq },
-            '!' => {
-                consume_while(&mut chars, |c| c != '\n');
-                continue;
-            },
-            _ if c.is_whitespace() => {
-                if c == '\n' {
-                    line += 1;
-                }
-                chars.next();
-                continue;
-            },
-            _ => return Err((format!("Unexpected character: {}", c), Location { line }))
-        };
-        tokens.push((token, Location { line }));
+pub fn tokenize_caret<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
+    chars.next();
+    if let Some(&'^') = chars.peek() {
+        chars.next();
+        Token::CaretCaret
+    } else {
+        Token::Carat
     }
-
-    Ok(tokens)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_consume_while() {
-        let mut chars = "hello world".chars().peekable();
-        let result = consume_while(&mut chars, |c| c.is_alphabetic());
-        assert_eq!(result, "hello");
+pub fn tokenize_and<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
+    chars.next();
+    if let Some(&'&') = chars.peek() {
+        chars.next();
+        Token::AndAnd
+    } else {
+        Token::And
     }
+}
 
-    #[test]
-    fn literal_int() {
-        let tokens = tokenize("123").unwrap();
-        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::Int { value: 123 }), Location { line: 0 })]);
+pub fn tokenize_pipe<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Token {
+    chars.next();
+    if let Some(&'|') = chars.peek() {
+        chars.next();
+        Token::OrOr
+    } else {
+        Token::Pipe
     }
+}
 
-    #[test]
-    fn literal_float() {
-        let tokens = tokenize("123.456").unwrap();
-        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::Float { value: 123.456 }), Location { line: 0 })]);
+/// The longest of the configured line comment markers `chars` starts with
+fn line_comment_marker<'a, I: Iterator<Item = char> + Clone>(chars: &Peekable<I>, config: &'a LexerConfig) -> Option<&'a str> {
+    config.line_comment_tokens.iter()
+        .filter(|marker| !marker.is_empty() && chars.clone().take(marker.chars().count()).eq(marker.chars()))
+        .max_by_key(|marker| marker.len())
+        .map(String::as_str)
+}
+
+/// Skip a line comment, which `chars` is known to start with `marker` for. If it starts with the
+/// configured directive prefix, the rest of the comment is returned as a `Directive` token instead
+pub fn tokenize_comment<I: Iterator<Item = char>>(chars: &mut Peekable<I>, marker: &str, config: &LexerConfig) -> Option<Token> {
+    for _ in marker.chars() {
+        chars.next();
     }
+    let comment = consume_while(chars, |c| c != '\n');
+    let prefix = config.directive_prefix.as_deref()?;
+    let directive = comment.strip_prefix(prefix)?;
+    Some(Token::Directive(directive.trim_end().to_string()))
+}
 
-    #[test]
-    fn literal_string() {
-        let tokens = tokenize("\"hello world\"").unwrap();
-        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::String { value: "hello world".to_string() }), Location { line: 0 })]);
+/// Try to read a regex literal `/pattern/flags` from the iterator. The pattern must be closed on
+/// the same line; if it isn't, nothing is consumed and None is returned so the caller can fall
+/// back to `Divide`. Escapes in the pattern are kept as written.
+pub fn tokenize_regex<I: Iterator<Item = char> + Clone>(chars: &mut Peekable<I>) -> Option<Token> {
+    let mut lookahead = chars.clone();
+    lookahead.next();
+    let mut pattern = String::new();
+    loop {
+        match lookahead.next() {
+            Some('/') => break,
+            Some('\\') => {
+                pattern.push('\\');
+                match lookahead.next() {
+                    Some(c) if c != '\n' => pattern.push(c),
+                    _ => return None,
+                }
+            }
+            Some(c) if c != '\n' => pattern.push(c),
+            _ => return None,
+        }
+    }
+    let flags = consume_while(&mut lookahead, |c| c.is_ascii_alphabetic());
+    // the pattern keeps escapes verbatim, so it has exactly one char per char consumed
+    for _ in 0..pattern.chars().count() + flags.chars().count() + 2 {
+        chars.next();
     }
+    Some(Token::Literal(LiteralKind::Regex { pattern, flags }))
+}
 
-    #[test]
-    fn literal_char() {
-        let tokens = tokenize("'a'").unwrap();
-        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::Char { value: 'a' }), Location { line: 0 })]);
+/// A `/` starts a regex literal unless it follows a value, in which case it's a division. With
+/// `integer_division`, `//` is always `SlashSlash`
+pub fn tokenize_slash<I: Iterator<Item = char> + Clone>(chars: &mut Peekable<I>, previous: Option<&Token>, config: &LexerConfig) -> Token {
+    if config.integer_division && is_doubled(chars, '/') {
+        chars.next();
+        chars.next();
+        return Token::SlashSlash;
+    }
+    if !previous.is_some_and(Token::is_value) {
+        if let Some(token) = tokenize_regex(chars) {
+            return token;
+        }
     }
+    chars.next();
+    Token::Divide
+}
 
-    #[test]
-    fn identifier() {
-        let tokens = tokenize("hello").unwrap();
-        assert_eq!(tokens, vec![(Token::Identifier("hello".to_string()), Location { line: 0 })]);
+/// Byte offset and line of a position in the source
+#[derive(Debug, Clone, Copy, Default)]
+struct Cursor {
+    offset: usize,
+    line: usize
+}
+
+/// Iterator adapter that records how far it has read into a shared cell, so the lexer can still
+/// read its position once it's wrapped in a `Peekable`. Clones get their own cell so that
+/// lookahead doesn't move the position, which means helpers have to advance the original
+/// iterator instead of replacing it with a lookahead clone.
+struct Tracked<I> {
+    inner: I,
+    cursor: Rc<Cell<Cursor>>
+}
+
+impl<I: Clone> Clone for Tracked<I> {
+    fn clone(&self) -> Self {
+        Tracked { inner: self.inner.clone(), cursor: Rc::new(Cell::new(self.cursor.get())) }
     }
+}
 
-    #[test]
-    fn binary_operators() {
-        let tokens = tokenize("+-*/^").unwrap();
-        assert_eq!(tokens, vec![
-            (Token::Plus, Location { line: 0 }),
-            (Token::Minus, Location { line: 0 }),
-            (Token::Multiply, Location { line: 0 }),
-            (Token::Divide, Location { line: 0 }),
-            (Token::Carat, Location { line: 0 })
-        ]);
+impl<I: Iterator<Item = char>> Iterator for Tracked<I> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.inner.next()?;
+        let Cursor { offset, line } = self.cursor.get();
+        self.cursor.set(Cursor { offset: offset + c.len_utf8(), line: line + (c == '\n') as usize });
+        Some(c)
     }
+}
 
-    #[test]
-    fn logical_operators() {
-        let tokens = tokenize("<>&=").unwrap();
-        assert_eq!(tokens, vec![
-            (Token::LessThan, Location { line: 0 }),
-            (Token::GreaterThan, Location { line: 0 }),
-            (Token::And, Location { line: 0 }),
-            (Token::Eq, Location { line: 0 })
-        ]);
+/// Position of the next char, not counting a char that has only been peeked
+fn position<I: Iterator<Item = char>>(chars: &mut Peekable<I>, cursor: &Cell<Cursor>) -> Cursor {
+    let peeked = chars.peek().copied();
+    let Cursor { offset, line } = cursor.get();
+    match peeked {
+        Some(c) => Cursor { offset: offset - c.len_utf8(), line: line - (c == '\n') as usize },
+        None => Cursor { offset, line },
     }
+}
 
-    // all other tokens work the same way, so if the above tests pass, the rest should work too
+/// How many tokens `tokenize_with_progress` lexes between progress reports
+pub const PROGRESS_INTERVAL: usize = 1024;
 
-    #[test]
-    fn arrow_vs_minus() {
-        let tokens = tokenize("-> -").unwrap();
-        assert_eq!(tokens, vec![
-            (Token::Arrow, Location { line: 0 }),
-            (Token::Minus, Location { line: 0 })
-        ]);
-    }
+/// Tokenize the input string and return every token with both its location and span
+fn lex<I: Iterator<Item = char> + Clone>(input: I, config: &LexerConfig) -> Result<Vec<(Token, Location, Span)>, LexError> {
+    lex_with_progress(input, config, |_| {}, &mut Vec::new(), None)
+}
 
-    #[test]
+/// Same as `lex`, calling `on_progress` with the current byte offset every `PROGRESS_INTERVAL`
+/// tokens and once more with the final offset when done. `extension` is tried first at every
+/// byte offset, and gives back a token and the offset it ends at
+fn lex_with_progress<I, P>(
+    input: I,
+    config: &LexerConfig,
+    on_progress: P,
+    warnings: &mut Vec<Diagnostic>,
+    extension: Option<&mut dyn FnMut(usize) -> Option<(Token, usize)>>,
+) -> Result<Vec<(Token, Location, Span)>, LexError>
+where
+    I: Iterator<Item = char> + Clone,
+    P: FnMut(usize),
+{
+    let mut tokens = Vec::new();
+    lex_into(input, config, on_progress, warnings, extension, |token, location, span| tokens.push((token, location, span)))?;
+    Ok(tokens)
+}
+
+/// Where lexing is between two tokens, so it can stop after any token and pick up again
+struct LexState<I: Iterator<Item = char>> {
+    chars: Peekable<Tracked<I>>,
+    cursor: Rc<Cell<Cursor>>,
+    /// The last token lexed, held back since `/`, `!` and `collapse_unit` look at it
+    pending: Option<(Token, Location, Span)>,
+    count: usize,
+}
+
+impl<I: Iterator<Item = char> + Clone> LexState<I> {
+    fn new(input: I) -> Self {
+        let cursor = Rc::new(Cell::new(Cursor::default()));
+        LexState { chars: Tracked { inner: input, cursor: cursor.clone() }.peekable(), cursor, pending: None, count: 0 }
+    }
+
+    /// Make `token` the pending one, giving back the previously pending token
+    fn push(&mut self, token: (Token, Location, Span)) -> Option<(Token, Location, Span)> {
+        self.count += 1;
+        self.pending.replace(token)
+    }
+
+    /// Lex until the next token is final and return it, None at the end of the input. On an
+    /// error the pending token is left in `pending` for the caller
+    fn next_token<P: FnMut(usize)>(
+        &mut self,
+        config: &LexerConfig,
+        on_progress: &mut P,
+        warnings: &mut Vec<Diagnostic>,
+        extension: &mut Option<&mut dyn FnMut(usize) -> Option<(Token, usize)>>,
+    ) -> Result<Option<(Token, Location, Span)>, LexError> {
+        while let Some(&c) = self.chars.peek() {
+            if config.eof_marker == Some(c) {
+                break;
+            }
+            let Cursor { offset: start, line } = position(&mut self.chars, &self.cursor);
+            let error = |kind| LexError { kind, location: Location { line } };
+            if let Some((token, end)) = extension.as_mut().and_then(|extension| extension(start)).filter(|&(_, end)| end > start) {
+                while position(&mut self.chars, &self.cursor).offset < end {
+                    self.chars.next();
+                }
+                if let Some(token) = self.push((token, Location { line }, Span { start, end })) {
+                    return Ok(Some(token));
+                }
+                continue;
+            }
+            if config.postfix_bang && c == '!' && self.pending.as_ref().is_some_and(|(token, _, span)| token.is_value() && span.end == start) {
+                self.chars.next();
+                if let Some(token) = self.push((Token::Bang, Location { line }, Span { start, end: start + 1 })) {
+                    return Ok(Some(token));
+                }
+                continue;
+            }
+            if let Some(marker) = line_comment_marker(&self.chars, config) {
+                if let Some(token) = tokenize_comment(&mut self.chars, marker, config) {
+                    let end = position(&mut self.chars, &self.cursor).offset;
+                    if let Some(token) = self.push((token, Location { line }, Span { start, end })) {
+                        return Ok(Some(token));
+                    }
+                }
+                continue;
+            }
+            let token = match c {
+                '0'..='9' => tokenize_number(&mut self.chars, config).map_err(error)?,
+                '"' => tokenize_string(&mut self.chars, config).map_err(error)?,
+                '\'' => match tokenize_label(&mut self.chars) {
+                    Some(token) => token,
+                    None => {
+                        let (token, truncated) = tokenize_char(&mut self.chars, config).map_err(error)?;
+                        if let Some(character) = truncated {
+                            warnings.push(Diagnostic {
+                                message: format!("Char literal '{}' truncated to its first character", character),
+                                location: Location { line },
+                                severity: Severity::Warning,
+                            });
+                        }
+                        token
+                    }
+                },
+                'a'..='z' | 'A'..='Z' => tokenize_identifier(&mut self.chars, config),
+                '+' => { self.chars.next(); Token::Plus },
+                '-' => tokenize_minus(&mut self.chars),
+                '*' => { self.chars.next(); Token::Multiply },
+                '/' => tokenize_slash(&mut self.chars, self.pending.as_ref().map(|(token, _, _)| token), config),
+                '^' => tokenize_caret(&mut self.chars),
+                '(' => { self.chars.next(); Token::LParen },
+                ')' => { self.chars.next(); Token::RParen },
+                '{' | '}' => tokenize_brace(&mut self.chars, config),
+                '[' => { self.chars.next(); Token::LBracket },
+                ']' => { self.chars.next(); Token::RBracket },
+                '#' => tokenize_hash(&mut self.chars),
+                '@' => { self.chars.next(); Token::At },
+                ':' => { self.chars.next(); Token::Colon },
+                ',' => { self.chars.next(); Token::Comma },
+                '.' => tokenize_dot(&mut self.chars),
+                '?' => tokenize_question(&mut self.chars),
+                ';' => { self.chars.next(); Token::Semi },
+                '<' => tokenize_less(&mut self.chars),
+                '>' => { self.chars.next(); Token::GreaterThan },
+                '&' => tokenize_and(&mut self.chars),
+                '|' => tokenize_pipe(&mut self.chars),
+                '=' => { self.chars.next(); Token::Eq },
+                _ if c.is_whitespace() => {
+                    self.chars.next();
+                    continue;
+                },
+                _ => return Err(error(LexErrorKind::UnexpectedCharacter(c)))
+            };
+            let end = position(&mut self.chars, &self.cursor).offset;
+            if config.collapse_unit && token == Token::RParen {
+                if let Some(last) = self.pending.as_mut().filter(|(token, _, span)| *token == Token::LParen && span.end == start) {
+                    last.0 = Token::Unit;
+                    last.2.end = end;
+                    continue;
+                }
+            }
+            let previous = self.push((token, Location { line }, Span { start, end }));
+            if self.count.is_multiple_of(PROGRESS_INTERVAL) {
+                on_progress(end);
+            }
+            if previous.is_some() {
+                return Ok(previous);
+            }
+        }
+        Ok(self.pending.take())
+    }
+}
+
+/// Same as `lex_with_progress`, handing every token to `emit` as soon as it's final instead of
+/// collecting them
+fn lex_into<I, P, E>(
+    input: I,
+    config: &LexerConfig,
+    mut on_progress: P,
+    warnings: &mut Vec<Diagnostic>,
+    mut extension: Option<&mut dyn FnMut(usize) -> Option<(Token, usize)>>,
+    mut emit: E,
+) -> Result<(), LexError>
+where
+    I: Iterator<Item = char> + Clone,
+    P: FnMut(usize),
+    E: FnMut(Token, Location, Span),
+{
+    let mut state = LexState::new(input);
+    loop {
+        match state.next_token(config, &mut on_progress, warnings, &mut extension) {
+            Ok(Some((token, location, span))) => emit(token, location, span),
+            Ok(None) => break,
+            Err(error) => {
+                if let Some((token, location, span)) = state.pending.take() {
+                    emit(token, location, span);
+                }
+                return Err(error);
+            }
+        }
+    }
+    on_progress(position(&mut state.chars, &state.cursor).offset);
+    Ok(())
+}
+
+/// Receives tokens one at a time from `lex_events`
+pub trait TokenVisitor {
+    fn on_token(&mut self, token: &Token, location: &Location);
+
+    /// Called with the error that stopped lexing, after every token before it was visited
+    fn on_error(&mut self, _error: &LexError) {}
+}
+
+/// Lex `input`, handing each token to `visitor` as it's lexed rather than collecting them
+pub fn lex_events(input: &str, visitor: &mut impl TokenVisitor) -> Result<(), LexError> {
+    let result = lex_into(input.chars(), &LexerConfig::default(), |_| {}, &mut Vec::new(), None, |token, location, _| {
+        visitor.on_token(&token, &location)
+    });
+    if let Err(error) = &result {
+        visitor.on_error(error);
+    }
+    result
+}
+
+/// Lex `input` into a closure lexing and returning the next token on every call, then the error
+/// if lexing failed, then None
+pub fn lex_pull(input: &str) -> impl FnMut() -> Option<Result<(Token, Location), LexError>> + '_ {
+    let config = LexerConfig::default();
+    let mut state = LexState::new(input.chars());
+    let mut error = None;
+    let mut done = false;
+    move || {
+        if let Some(error) = error.take() {
+            return Some(Err(error));
+        }
+        if done {
+            return None;
+        }
+        let next = state.next_token(&config, &mut |_| {}, &mut Vec::new(), &mut None);
+        match next {
+            Ok(Some((token, location, _))) => Some(Ok((token, location))),
+            Ok(None) => {
+                done = true;
+                None
+            }
+            Err(next) => {
+                done = true;
+                match state.pending.take() {
+                    Some((token, location, _)) => {
+                        error = Some(next);
+                        Some(Ok((token, location)))
+                    }
+                    None => Some(Err(next)),
+                }
+            }
+        }
+    }
+}
+
+/// Custom lexing hook registered with `Lexer::with_extension`
+type Extension = Box<dyn FnMut(&mut Peekable<Chars<'_>>) -> Option<Token>>;
+
+/// Lexer over any `Source`, for callers whose text isn't stored as one contiguous `&str`
+pub struct Lexer<S: Source> {
+    source: S,
+    config: LexerConfig,
+    extension: Option<RefCell<Extension>>
+}
+
+impl<S: Source> Lexer<S> {
+    pub fn new(source: S) -> Self {
+        Lexer { source, config: LexerConfig::default(), extension: None }
+    }
+
+    pub fn with_config(mut self, config: LexerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Give `extension` the first try at the rest of the input at every position, before any
+    /// built-in token. Whatever it consumes before returning a token becomes that token, when it
+    /// returns None or consumes nothing the input is lexed as usual. Meant for prototyping new
+    /// syntax, since the source is copied into one string for it and every match costs a pass
+    /// over the rest
+    pub fn with_extension(mut self, extension: impl FnMut(&mut Peekable<Chars<'_>>) -> Option<Token> + 'static) -> Self {
+        self.extension = Some(RefCell::new(Box::new(extension)));
+        self
+    }
+
+    fn lex(&self) -> Result<Vec<(Token, Location, Span)>, LexError> {
+        let Some(extension) = &self.extension else {
+            return lex(self.source.chars_from(0), &self.config);
+        };
+        let text: String = self.source.chars_from(0).collect();
+        let mut extension = extension.borrow_mut();
+        let mut at_offset = |offset: usize| {
+            let mut chars = text[offset..].chars().peekable();
+            let token = extension(&mut chars)?;
+            let rest: usize = chars.map(char::len_utf8).sum();
+            Some((token, text.len() - rest))
+        };
+        lex_with_progress(text.chars(), &self.config, |_| {}, &mut Vec::new(), Some(&mut at_offset))
+    }
+
+    pub fn tokenize(&self) -> Result<Vec<(Token, Location)>, LexError> {
+        let tokens = self.lex()?;
+        Ok(tokens.into_iter().map(|(token, location, _)| (token, location)).collect())
+    }
+
+    pub fn tokenize_spanned(&self) -> Result<Vec<(Token, Span)>, LexError> {
+        let tokens = self.lex()?;
+        Ok(tokens.into_iter().map(|(token, _, span)| (token, span)).collect())
+    }
+}
+
+/// Tokenize the input string and return a vector of tokens with their locations
+pub fn tokenize<T: AsRef<str>>(input: T) -> Result<Vec<(Token, Location)>, LexError> {
+    tokenize_with_config(input, &LexerConfig::default())
+}
+
+/// Same as `tokenize`, but with a custom lexer configuration
+pub fn tokenize_with_config<T: AsRef<str>>(input: T, config: &LexerConfig) -> Result<Vec<(Token, Location)>, LexError> {
+    let tokens = lex(input.as_ref().chars(), config)?;
+    Ok(tokens.into_iter().map(|(token, location, _)| (token, location)).collect())
+}
+
+/// Tokenize the input string and return a vector of tokens with their byte spans
+pub fn tokenize_spanned<T: AsRef<str>>(input: T) -> Result<Vec<(Token, Span)>, LexError> {
+    let tokens = lex(input.as_ref().chars(), &LexerConfig::default())?;
+    Ok(tokens.into_iter().map(|(token, _, span)| (token, span)).collect())
+}
+
+/// Same as `tokenize`, reporting the byte offset lexed so far to `on_progress` every
+/// `PROGRESS_INTERVAL` tokens, and the final offset once the whole input is lexed
+pub fn tokenize_with_progress(input: &str, on_progress: impl FnMut(usize)) -> Result<Vec<(Token, Location)>, LexError> {
+    let tokens = lex_with_progress(input.chars(), &LexerConfig::default(), on_progress, &mut Vec::new(), None)?;
+    Ok(tokens.into_iter().map(|(token, location, _)| (token, location)).collect())
+}
+
+/// Same as `tokenize_with_config`, also returning the warnings for input that was accepted but
+/// is likely a mistake, like a truncated char literal under `CharPolicy::FirstChar`
+#[allow(clippy::type_complexity)]
+pub fn tokenize_with_warnings(input: &str, config: &LexerConfig) -> Result<(Vec<(Token, Location)>, Vec<Diagnostic>), LexError> {
+    let mut warnings = Vec::new();
+    let tokens = lex_with_progress(input.chars(), config, |_| {}, &mut warnings, None)?;
+    Ok((tokens.into_iter().map(|(token, location, _)| (token, location)).collect(), warnings))
+}
+
+/// Same as `tokenize`, reading the input from an async stream. Nothing is lexed until the stream
+/// ends, so strings and comments split across chunks are buffered until they're complete
+#[cfg(feature = "async")]
+pub async fn tokenize_stream<S: futures::Stream<Item = char>>(stream: S) -> Result<Vec<(Token, Location)>, LexError> {
+    use futures::StreamExt;
+    let input: String = stream.collect().await;
+    tokenize(input)
+}
+
+/// Same as `tokenize`, but fails at the first token whose kind isn't in `allowed`, without
+/// lexing any further
+pub fn tokenize_restricted<T: AsRef<str>>(input: T, allowed: &HashSet<TokenKind>) -> Result<Vec<(Token, Location)>, LexError> {
+    let config = LexerConfig::default();
+    let check = |(token, location, _): (Token, Location, Span)| {
+        if !allowed.contains(&token.kind()) {
+            return Err(LexError { kind: LexErrorKind::DisallowedToken(token.kind()), location });
+        }
+        Ok((token, location))
+    };
+    let mut state = LexState::new(input.as_ref().chars());
+    let mut tokens = Vec::new();
+    loop {
+        match state.next_token(&config, &mut |_| {}, &mut Vec::new(), &mut None) {
+            Ok(Some(token)) => tokens.push(check(token)?),
+            Ok(None) => return Ok(tokens),
+            Err(error) => {
+                // the token before the error is still pending, and comes first
+                if let Some(token) = state.pending.take() {
+                    check(token)?;
+                }
+                return Err(error);
+            }
+        }
+    }
+}
+
+/// Token spans as a flat `[start0, end0, start1, end1, ...]` array of byte offsets, which is
+/// simpler to hand across an FFI boundary than a vector of structs
+pub fn token_positions(input: &str) -> Result<Vec<u32>, LexError> {
+    let tokens = tokenize_spanned(input)?;
+    Ok(tokens.iter().flat_map(|(_, span)| [span.start as u32, span.end as u32]).collect())
+}
+
+/// Same as `tokenize`, lexing each distinct line only once, for inputs with many repeated lines
+/// like generated code. Lines are lexed on their own, so if any line doesn't lex by itself (e.g.
+/// it starts a multiline string) or starts with a `/`, whose meaning depends on the line before,
+/// the whole input is lexed normally instead
+pub fn tokenize_memoized(input: &str) -> Result<Vec<(Token, Location)>, LexError> {
+    tokenize_memoized_counting(input).map(|(tokens, _)| tokens)
+}
+
+/// `tokenize_memoized`, also returning how many lines were found in the cache
+fn tokenize_memoized_counting(input: &str) -> Result<(Vec<(Token, Location)>, usize), LexError> {
+    let mut cache: HashMap<&str, Vec<Token>> = HashMap::new();
+    let mut hits = 0;
+    let mut result = Vec::new();
+    for (line, text) in input.split('\n').enumerate() {
+        let tokens = match cache.get(text) {
+            Some(tokens) => {
+                hits += 1;
+                tokens
+            }
+            None => {
+                let lexed = match tokenize(text) {
+                    Ok(lexed) if !matches!(lexed.first(), Some((Token::Divide | Token::Literal(LiteralKind::Regex { .. }), _))) => lexed,
+                    _ => return Ok((tokenize(input)?, 0)),
+                };
+                cache.entry(text).or_insert(lexed.into_iter().map(|(token, _)| token).collect())
+            }
+        };
+        result.extend(tokens.iter().map(|token| (token.clone(), Location { line })));
+    }
+    Ok((result, hits))
+}
+
+/// Same as `tokenize`, giving every token a `TokenId` counting up from 0
+pub fn tokenize_with_ids(input: &str) -> Result<Vec<(TokenId, Token, Location)>, LexError> {
+    let tokens = tokenize(input)?;
+    Ok(tokens.into_iter().zip(0..).map(|((token, location), id)| (TokenId(id), token, location)).collect())
+}
+
+/// Same as `tokenize`, with automatic semicolon insertion: a `Semi` is added at a line break if
+/// the line ends in something that can end a statement (a value or a `}`) and the next line
+/// doesn't start with an operator continuing the expression, like `+` or `.`
+pub fn tokenize_asi(input: &str) -> Result<Vec<(Token, Location)>, LexError> {
+    let continues = |token: &Token| matches!(token,
+        Token::Plus | Token::Minus | Token::Multiply | Token::Divide | Token::SlashSlash | Token::Carat
+        | Token::CaretCaret | Token::LessThan | Token::LessEq | Token::Spaceship | Token::Shl | Token::GreaterThan
+        | Token::AndAnd | Token::OrOr | Token::Pipe | Token::Eq | Token::Arrow | Token::Dot | Token::DotDot
+        | Token::QuestionDot | Token::Question | Token::Colon | Token::Comma);
+    let tokens = tokenize(input)?;
+    let mut result = Vec::with_capacity(tokens.len());
+    for (index, (token, location)) in tokens.iter().enumerate() {
+        result.push((token.clone(), *location));
+        let Some((next, next_location)) = tokens.get(index + 1) else {
+            continue;
+        };
+        let ends_statement = token.is_value() || *token == Token::RBrace;
+        if next_location.line > location.line && ends_statement && !continues(next) {
+            result.push((Token::Semi, *location));
+        }
+    }
+    Ok(result)
+}
+
+/// Lex only the bytes of `input` in `range`, e.g. a code block inside a larger document. Spans are
+/// relative to the whole of `input`, and lines (of tokens and errors) are offset by `base_line`,
+/// the line the range starts on. A range that isn't within `input` or doesn't fall on char
+/// boundaries is an `InvalidRange` error on `base_line`
+pub fn tokenize_subrange(input: &str, range: Range<usize>, base_line: usize) -> Result<Vec<(Token, Location, Span)>, LexError> {
+    let start = range.start;
+    let Some(text) = input.get(range.clone()) else {
+        return Err(LexError { kind: LexErrorKind::InvalidRange(range), location: Location { line: base_line } });
+    };
+    let tokens = lex(text.chars(), &LexerConfig::default())
+        .map_err(|error| LexError { location: Location { line: error.location.line + base_line }, ..error })?;
+    Ok(tokens.into_iter()
+        .map(|(token, location, span)| {
+            (token, Location { line: location.line + base_line }, Span { start: span.start + start, end: span.end + start })
+        })
+        .collect())
+}
+
+/// Lex `input` twice and panic if the tokens, locations, spans or error differ between the runs.
+/// Useful in fuzzers as a guard against hidden nondeterminism in the lexer
+pub fn assert_deterministic(input: &str) {
+    let first = lex(input.chars(), &LexerConfig::default());
+    let second = lex(input.chars(), &LexerConfig::default());
+    assert_eq!(first, second, "lexing {:?} twice gave different results", input);
+}
+
+/// Shrink an input that fails to lex to a short one failing with the same kind of error, for bug
+/// reports. The input is first cut to its shortest failing prefix, then chunks of it are dropped,
+/// halving the chunk size down to single characters, as long as the error kind stays the same.
+/// None if `input` lexes fine
+pub fn minimize_error(input: &str) -> Option<String> {
+    let kind = std::mem::discriminant(&tokenize(input).err()?.kind);
+    let fails = |chars: &[char]| {
+        tokenize(chars.iter().collect::<String>()).is_err_and(|error| std::mem::discriminant(&error.kind) == kind)
+    };
+    let chars: Vec<char> = input.chars().collect();
+    let end = (1..=chars.len()).find(|&end| fails(&chars[..end])).unwrap_or(chars.len());
+    let mut chars = chars[..end].to_vec();
+    let mut chunk = chars.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < chars.len() {
+            let mut candidate = chars.clone();
+            candidate.drain(start..(start + chunk).min(chars.len()));
+            if fails(&candidate) {
+                chars = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+    Some(chars.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::Rope;
+
+    #[test]
+    fn test_consume_while() {
+        let mut chars = "hello world".chars().peekable();
+        let result = consume_while(&mut chars, |c| c.is_alphabetic());
+        assert_eq!(result, "hello");
+    }
+
+    #[test]
+    fn literal_int() {
+        let tokens = tokenize("123").unwrap();
+        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::int(123)), Location { line: 0 })]);
+    }
+
+    #[test]
+    fn literal_float() {
+        let tokens = tokenize("123.456").unwrap();
+        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::Float { value: 123.456 }), Location { line: 0 })]);
+    }
+
+    #[test]
+    fn float_requires_digit_after_separator() {
+        let tokens = tokenize("1.x").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::int(1)), Location { line: 0 }),
+            (Token::Dot, Location { line: 0 }),
+            (Token::Identifier("x".to_string()), Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn comma_decimal_separator() {
+        let config = LexerConfig { decimal_separator: ',', ..LexerConfig::default() };
+        let tokens = tokenize_with_config("3,14 1.2", &config).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::Float { value: 3.14 }), Location { line: 0 }),
+            (Token::Literal(LiteralKind::int(1)), Location { line: 0 }),
+            (Token::Dot, Location { line: 0 }),
+            (Token::Literal(LiteralKind::int(2)), Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn comma_thousands() {
+        let config = LexerConfig { comma_thousands: true, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("1,000 12,345,678.5", &config).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::Int { value: 1000, radix: Radix::Decimal, raw: "1,000".to_string() }), Location { line: 0 }),
+            (Token::Literal(LiteralKind::Float { value: 12345678.5 }), Location { line: 0 })
+        ]);
+        let error = tokenize_with_config("1,00", &config).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::InvalidNumber("1,00".to_string()));
+        assert!(tokenize_with_config("1234,567", &config).is_err());
+    }
+
+    #[test]
+    fn leading_zero_policies() {
+        let lex_with = |leading_zero| tokenize_with_config("0123", &LexerConfig { leading_zero, ..LexerConfig::default() });
+        assert_eq!(lex_with(LeadingZeroPolicy::Decimal).unwrap()[0].0, Token::Literal(LiteralKind::Int { value: 123, radix: Radix::Decimal, raw: "0123".to_string() }));
+        assert_eq!(lex_with(LeadingZeroPolicy::Octal).unwrap()[0].0, Token::Literal(LiteralKind::Int { value: 83, radix: Radix::Octal, raw: "0123".to_string() }));
+        assert_eq!(lex_with(LeadingZeroPolicy::Error).unwrap_err().kind, LexErrorKind::LeadingZero("0123".to_string()));
+        let config = LexerConfig { leading_zero: LeadingZeroPolicy::Error, ..LexerConfig::default() };
+        assert!(tokenize_with_config("0 0.5", &config).is_ok());
+    }
+
+    #[test]
+    fn radix_prefixes() {
+        let config = LexerConfig { radix_prefixes: true, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("0xFF 0b101 0o17 0", &config).unwrap();
+        assert_eq!(tokens[0].0, Token::Literal(LiteralKind::Int { value: 255, radix: Radix::Hex, raw: "0xFF".to_string() }));
+        assert_eq!(tokens[1].0, Token::Literal(LiteralKind::Int { value: 5, radix: Radix::Binary, raw: "0b101".to_string() }));
+        assert_eq!(tokens[2].0, Token::Literal(LiteralKind::Int { value: 15, radix: Radix::Octal, raw: "0o17".to_string() }));
+        assert_eq!(tokens[3].0, Token::Literal(LiteralKind::int(0)));
+        assert_eq!(tokens[0].0.to_string(), "0xFF");
+        assert_eq!(tokenize_with_config("0b102", &config).unwrap_err().kind, LexErrorKind::InvalidNumber("0b102".to_string()));
+        assert_eq!(tokenize_with_config("0x", &config).unwrap_err().kind, LexErrorKind::InvalidNumber("0x".to_string()));
+    }
+
+    #[test]
+    fn imaginary_literals() {
+        let tokens = tokenize("3i 2+1.5i").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::Imaginary { value: 3.0 }), Location { line: 0 }),
+            (Token::Literal(LiteralKind::int(2)), Location { line: 0 }),
+            (Token::Plus, Location { line: 0 }),
+            (Token::Literal(LiteralKind::Imaginary { value: 1.5 }), Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn number_before_identifier_starting_with_i() {
+        let tokens = tokenize("3item").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::int(3)), Location { line: 0 }),
+            (Token::Identifier("item".to_string()), Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn literal_string() {
+        let tokens = tokenize("\"hello world\"").unwrap();
+        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::String { value: "hello world".to_string() }), Location { line: 0 })]);
+    }
+
+    #[test]
+    fn literal_char() {
+        let tokens = tokenize("'a'").unwrap();
+        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::Char { value: 'a' }), Location { line: 0 })]);
+    }
+
+    #[test]
+    fn label_vs_char_literal() {
+        let tokens = tokenize("'outer: 'a' 'b").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Label("outer".to_string()), Location { line: 0 }),
+            (Token::Colon, Location { line: 0 }),
+            (Token::Literal(LiteralKind::Char { value: 'a' }), Location { line: 0 }),
+            (Token::Label("b".to_string()), Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn string_escapes() {
+        let tokens = tokenize(r#""a\n\t\\\"\x41\u{e9}" '\''"#).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::String { value: "a\n\t\\\"A\u{e9}".to_string() }), Location { line: 0 }),
+            (Token::Literal(LiteralKind::Char { value: '\'' }), Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn invalid_escape() {
+        let error = tokenize(r#""\q""#).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::InvalidEscape("q".to_string()));
+    }
+
+    #[test]
+    fn surrogate_escape() {
+        let error = tokenize(r#""\u{D800}""#).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::SurrogateEscape(0xD800));
+        assert!(error.to_string().contains("surrogate"));
+    }
+
+    #[test]
+    fn malformed_input_errors_without_panicking() {
+        let corpus = [
+            "\"unterminated",
+            "\"escape at end\\",
+            "'",
+            "'\\n",
+            "'ab'",
+            "''",
+            "99999999999999999999",
+            "\"\\x\"",
+            "\"\\xZZ\"",
+            "\"\\xFF\"",
+            "\"\\u{}\"",
+            "\"\\u{110000}\"",
+            "\"\\u{1234567}\"",
+            "\"\\u1234\"",
+            "`",
+            "a = $",
+        ];
+        for input in corpus {
+            let result = std::panic::catch_unwind(|| tokenize(input));
+            assert!(matches!(result, Ok(Err(_))), "{:?} should fail to lex without panicking", input);
+        }
+    }
+
+    #[test]
+    fn multiline_string_allowed() {
+        let tokens = tokenize("\"a\nb\" c").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::String { value: "a\nb".to_string() }), Location { line: 0 }),
+            (Token::Identifier("c".to_string()), Location { line: 1 })
+        ]);
+    }
+
+    #[test]
+    fn multiline_string_rejected() {
+        let config = LexerConfig { allow_multiline_strings: false, ..LexerConfig::default() };
+        let error = tokenize_with_config("\"a\nb\"", &config).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::NewlineInString);
+        assert!(tokenize_with_config("\"a\\nb\"", &config).is_ok());
+    }
+
+    #[test]
+    fn max_string_len() {
+        let config = LexerConfig { max_string_len: Some(5), ..LexerConfig::default() };
+        assert!(tokenize_with_config("\"abcde\"", &config).is_ok());
+        assert!(tokenize_with_config(r#""ab\ncd""#, &config).is_ok());
+        let error = tokenize_with_config("\"abcdef\"", &config).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::StringTooLong(5));
+    }
+
+    #[test]
+    fn null_keyword() {
+        let config = LexerConfig { null_keyword: Some("none".to_string()), ..LexerConfig::default() };
+        assert_eq!(tokenize_with_config("none", &config).unwrap(), vec![(Token::Literal(LiteralKind::Null), Location { line: 0 })]);
+        assert_eq!(tokenize("none").unwrap(), vec![(Token::Identifier("none".to_string()), Location { line: 0 })]);
+    }
+
+    #[test]
+    fn identifier() {
+        let tokens = tokenize("hello").unwrap();
+        assert_eq!(tokens, vec![(Token::Identifier("hello".to_string()), Location { line: 0 })]);
+    }
+
+    #[test]
+    fn binary_operators() {
+        let tokens = tokenize("+-*/^").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Plus, Location { line: 0 }),
+            (Token::Minus, Location { line: 0 }),
+            (Token::Multiply, Location { line: 0 }),
+            (Token::Divide, Location { line: 0 }),
+            (Token::Carat, Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn logical_operators() {
+        let tokens = tokenize("<>&=").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::LessThan, Location { line: 0 }),
+            (Token::GreaterThan, Location { line: 0 }),
+            (Token::And, Location { line: 0 }),
+            (Token::Eq, Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn caret_vs_caret_caret() {
+        let tokens = tokenize("^^ ^").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::CaretCaret, Location { line: 0 }),
+            (Token::Carat, Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn nfc_identifiers() {
+        let config = LexerConfig { normalize_identifiers_nfc: true, ..LexerConfig::default() };
+        let composed = tokenize_with_config("caf\u{e9}", &config).unwrap();
+        let decomposed = tokenize_with_config("cafe\u{301}", &config).unwrap();
+        assert_eq!(composed, decomposed);
+        assert_eq!(composed, vec![(Token::Identifier("caf\u{e9}".to_string()), Location { line: 0 })]);
+        assert_ne!(tokenize("caf\u{e9}").unwrap(), tokenize("cafe\u{301}").unwrap());
+    }
+
+    #[test]
+    fn doubled_logical_operators() {
+        let tokens = tokenize("& && | ||").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::And, Location { line: 0 }),
+            (Token::AndAnd, Location { line: 0 }),
+            (Token::Pipe, Location { line: 0 }),
+            (Token::OrOr, Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn operator_aliases() {
+        let mut operator_aliases = HashMap::new();
+        operator_aliases.insert("and".to_string(), Token::AndAnd);
+        let config = LexerConfig { operator_aliases, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("a and b", &config).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Identifier("a".to_string()), Location { line: 0 }),
+            (Token::AndAnd, Location { line: 0 }),
+            (Token::Identifier("b".to_string()), Location { line: 0 })
+        ]);
+    }
+
+    // all other tokens work the same way, so if the above tests pass, the rest should work too
+
+    #[test]
+    fn collapse_unit() {
+        let config = LexerConfig { collapse_unit: true, ..LexerConfig::default() };
+        assert_eq!(tokenize_with_config("()", &config).unwrap(), vec![(Token::Unit, Location { line: 0 })]);
+        assert_eq!(tokenize("()").unwrap(), vec![
+            (Token::LParen, Location { line: 0 }),
+            (Token::RParen, Location { line: 0 })
+        ]);
+        assert_eq!(tokenize_with_config("(a)", &config).unwrap().len(), 3);
+        assert_eq!(tokenize_with_config("( )", &config).unwrap().len(), 2);
+        assert_eq!(tokenize_with_config("(! comment\n)", &config).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn arrow_vs_minus() {
+        let tokens = tokenize("-> -").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Arrow, Location { line: 0 }),
+            (Token::Minus, Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn regex_literal() {
+        let tokens = tokenize("/ab/i").unwrap();
+        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::Regex { pattern: "ab".to_string(), flags: "i".to_string() }), Location { line: 0 })]);
+    }
+
+    #[test]
+    fn regex_literal_with_escapes() {
+        let tokens = tokenize(r"= /\d+\//g").unwrap();
+        assert_eq!(tokens[1], (Token::Literal(LiteralKind::Regex { pattern: r"\d+\/".to_string(), flags: "g".to_string() }), Location { line: 0 }));
+    }
+
+    #[test]
+    fn slash_after_value_is_divide() {
+        let tokens = tokenize("a / b").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Identifier("a".to_string()), Location { line: 0 }),
+            (Token::Divide, Location { line: 0 }),
+            (Token::Identifier("b".to_string()), Location { line: 0 })
+        ]);
+    }
+
+    #[test]
     fn comments() {
         let tokens = tokenize("!hello world\n").unwrap();
         assert_eq!(tokens, vec![]);
     }
 
+    #[test]
+    fn unexpected_character() {
+        let error = tokenize("a\n$").unwrap_err();
+        assert_eq!(error, LexError { kind: LexErrorKind::UnexpectedCharacter('$'), location: Location { line: 1 } });
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = tokenize_spanned("ab + \"ü\"").unwrap();
+        let spans: Vec<Span> = tokens.into_iter().map(|(_, span)| span).collect();
+        assert_eq!(spans, vec![Span { start: 0, end: 2 }, Span { start: 3, end: 4 }, Span { start: 5, end: 9 }]);
+    }
+
+    #[test]
+    fn flat_token_positions() {
+        assert_eq!(token_positions("ab cd").unwrap(), vec![0, 2, 3, 5]);
+    }
+
+    #[test]
+    fn eof_marker() {
+        let config = LexerConfig { eof_marker: Some('\x04'), ..LexerConfig::default() };
+        let tokens = tokenize_with_config("a\x04b", &config).unwrap();
+        assert_eq!(tokens, vec![(Token::Identifier("a".to_string()), Location { line: 0 })]);
+    }
+
+    #[test]
+    fn lexing_a_rope() {
+        let input = "fib(n) = fib(n - 1) + \"sé\";\n! comment\nx";
+        let rope = Rope::from_chunks(["fib(n) = f", "ib(n - 1) + \"s", "é\";\n! com", "ment\nx"]);
+        assert_eq!(Lexer::new(rope.clone()).tokenize().unwrap(), Lexer::new(input).tokenize().unwrap());
+        assert_eq!(Lexer::new(rope).tokenize_spanned().unwrap(), tokenize_spanned(input).unwrap());
+    }
+
+    #[test]
+    fn directive_comments() {
+        let config = LexerConfig { directive_prefix: Some("pragma ".to_string()), ..LexerConfig::default() };
+        let tokens = tokenize_with_config("!pragma once\n! plain comment\na", &config).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Directive("once".to_string()), Location { line: 0 }),
+            (Token::Identifier("a".to_string()), Location { line: 2 })
+        ]);
+    }
+
+    #[test]
+    fn display_tokens() {
+        let tokens = tokenize("f(x) -> 1.0 + 'a' \"s\\n\" = /re/g").unwrap();
+        let text: Vec<String> = tokens.iter().map(|(token, _)| token.to_string()).collect();
+        assert_eq!(text, vec!["f", "(", "x", ")", "->", "1.0", "+", "'a'", "\"s\\n\"", "=", "/re/g"]);
+        let floats = [Token::Literal(LiteralKind::Float { value: 1e-6 }), Token::Literal(LiteralKind::Float { value: 1e21 })];
+        let text: Vec<String> = floats.iter().map(Token::to_string).collect();
+        assert_eq!(text, vec!["0.000001", "1000000000000000000000.0"]);
+        let lexed: Vec<Token> = tokenize(text.join(" ")).unwrap().into_iter().map(|(token, _)| token).collect();
+        assert_eq!(lexed, floats);
+    }
+
+    #[test]
+    fn progress_callback() {
+        let input = "a + b;\n".repeat(PROGRESS_INTERVAL);
+        let mut offsets = Vec::new();
+        let tokens = tokenize_with_progress(&input, |offset| offsets.push(offset)).unwrap();
+        assert_eq!(tokens.len(), PROGRESS_INTERVAL * 4);
+        assert!(offsets.len() > 1);
+        assert!(offsets.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(offsets.last(), Some(&input.len()));
+    }
+
+    #[test]
+    fn restricted_tokens() {
+        let allowed = HashSet::from([TokenKind::Identifier, TokenKind::Plus]);
+        assert!(tokenize_restricted("a + b", &allowed).is_ok());
+        let error = tokenize_restricted("a +\na * b", &allowed).unwrap_err();
+        assert_eq!(error, LexError { kind: LexErrorKind::DisallowedToken(TokenKind::Multiply), location: Location { line: 1 } });
+        let error = tokenize_restricted("a * b $", &allowed).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::DisallowedToken(TokenKind::Multiply));
+        let error = tokenize_restricted("a *$", &allowed).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::DisallowedToken(TokenKind::Multiply));
+    }
+
+    #[test]
+    fn raw_identifiers() {
+        let tokens = tokenize("r#let r#true r").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Identifier("let".to_string()), Location { line: 0 }),
+            (Token::Identifier("true".to_string()), Location { line: 0 }),
+            (Token::Identifier("r".to_string()), Location { line: 0 })
+        ]);
+        let text: Vec<String> = tokens.iter().map(|(token, _)| token.to_string()).collect();
+        assert_eq!(text, vec!["r#let", "r#true", "r"]);
+        assert_eq!(tokenize(text.join(" ")).unwrap(), tokens);
+        assert_eq!(tokenize("r#_x").unwrap_err().kind, LexErrorKind::UnexpectedCharacter('_'));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn lexing_a_stream() {
+        let input = "s = \"a b\" ! comment\nf(s)";
+        let stream = futures::stream::iter(input.chars().collect::<Vec<_>>());
+        let tokens = futures::executor::block_on(tokenize_stream(stream)).unwrap();
+        assert_eq!(tokens, tokenize(input).unwrap());
+    }
+
+    #[test]
+    fn guard_keyword_span() {
+        let tokens = tokenize_spanned("x when y").unwrap();
+        assert_eq!(tokens[1], (Token::Keyword("when".to_string()), Span { start: 2, end: 6 }));
+        assert_eq!(tokenize("r#where").unwrap()[0].0, Token::Identifier("where".to_string()));
+    }
+
+    #[test]
+    fn strict_char_literals() {
+        let error = tokenize("'ab'").unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::InvalidCharLiteral("ab".to_string()));
+    }
+
+    #[test]
+    fn first_char_literals() {
+        let config = LexerConfig { char_literal: CharPolicy::FirstChar, ..LexerConfig::default() };
+        let (tokens, warnings) = tokenize_with_warnings("'ab'", &config).unwrap();
+        assert_eq!(tokens, vec![(Token::Literal(LiteralKind::Char { value: 'a' }), Location { line: 0 })]);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        let (_, warnings) = tokenize_with_warnings("'a'", &config).unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn hash_brace() {
+        let tokens = tokenize("#{a} #").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::HashBrace, Location { line: 0 }),
+            (Token::Identifier("a".to_string()), Location { line: 0 }),
+            (Token::RBrace, Location { line: 0 }),
+            (Token::Hash, Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn arbitrary_radix() {
+        let config = LexerConfig { arbitrary_radix: true, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("16rFF 3r120 36rz", &config).unwrap();
+        assert_eq!(tokens[0].0, Token::Literal(LiteralKind::Int { value: 255, radix: Radix::Hex, raw: "16rFF".to_string() }));
+        assert_eq!(tokens[1].0, Token::Literal(LiteralKind::Int { value: 15, radix: Radix::Other(3), raw: "3r120".to_string() }));
+        assert_eq!(tokens[2].0, Token::Literal(LiteralKind::Int { value: 35, radix: Radix::Other(36), raw: "36rz".to_string() }));
+        assert_eq!(tokenize_with_config("2r102", &config).unwrap_err().kind, LexErrorKind::InvalidNumber("2r102".to_string()));
+        assert_eq!(tokenize_with_config("37r1", &config).unwrap_err().kind, LexErrorKind::InvalidNumber("37r1".to_string()));
+    }
+
+    #[test]
+    fn lexer_extension() {
+        let lexer = Lexer::new("a @@ b").with_extension(|chars| {
+            if chars.next() == Some('@') && chars.next() == Some('@') {
+                Some(Token::Identifier("at-at".to_string()))
+            } else {
+                None
+            }
+        });
+        assert_eq!(lexer.tokenize_spanned().unwrap(), vec![
+            (Token::Identifier("a".to_string()), Span { start: 0, end: 1 }),
+            (Token::Identifier("at-at".to_string()), Span { start: 2, end: 4 }),
+            (Token::Identifier("b".to_string()), Span { start: 5, end: 6 })
+        ]);
+    }
+
+    #[test]
+    fn lexing_a_subrange() {
+        let input = "# Title\n```\na = 1\nb\n```\n";
+        let tokens = tokenize_subrange(input, 12..20, 2).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Identifier("a".to_string()), Location { line: 2 }, Span { start: 12, end: 13 }),
+            (Token::Eq, Location { line: 2 }, Span { start: 14, end: 15 }),
+            (Token::Literal(LiteralKind::int(1)), Location { line: 2 }, Span { start: 16, end: 17 }),
+            (Token::Identifier("b".to_string()), Location { line: 3 }, Span { start: 18, end: 19 })
+        ]);
+        assert_eq!(tokenize_subrange("a\nb\n$", 4..5, 2).unwrap_err().location, Location { line: 2 });
+        assert_eq!(tokenize_subrange("aé", 0..2, 0).unwrap_err().kind, LexErrorKind::InvalidRange(0..2));
+        assert_eq!(tokenize_subrange("ab", 1..5, 0).unwrap_err().kind, LexErrorKind::InvalidRange(1..5));
+    }
+
+    #[test]
+    fn multiple_comment_markers() {
+        let config = LexerConfig { line_comment_tokens: vec!["!".to_string(), "--".to_string()], ..LexerConfig::default() };
+        let tokens = tokenize_with_config("! a\nx -- b\n-y", &config).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Identifier("x".to_string()), Location { line: 1 }),
+            (Token::Minus, Location { line: 2 }),
+            (Token::Identifier("y".to_string()), Location { line: 2 })
+        ]);
+        let config = LexerConfig { line_comment_tokens: vec!["--".to_string()], ..LexerConfig::default() };
+        assert_eq!(tokenize_with_config("!", &config).unwrap_err().kind, LexErrorKind::UnexpectedCharacter('!'));
+    }
+
+    #[test]
+    fn deterministic_lexing() {
+        let inputs = ["", "fib(n) = fib(n - 1) + fib(n - 2);", "'outer: \"s\\u{e9}\" ! comment\n#{a}", "0123 1.5 3i /re/g", "\"unterminated"];
+        for input in inputs {
+            assert_deterministic(input);
+        }
+    }
+
+    #[test]
+    fn optional_chaining() {
+        let tokens = tokenize("a?.b").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Identifier("a".to_string()), Location { line: 0 }),
+            (Token::QuestionDot, Location { line: 0 }),
+            (Token::Identifier("b".to_string()), Location { line: 0 })
+        ]);
+        let tokens = tokenize("a?").unwrap();
+        assert_eq!(tokens[1].0, Token::Question);
+    }
+
+    #[test]
+    fn automatic_semicolons() {
+        assert_eq!(tokenize_asi("a\nb").unwrap(), vec![
+            (Token::Identifier("a".to_string()), Location { line: 0 }),
+            (Token::Semi, Location { line: 0 }),
+            (Token::Identifier("b".to_string()), Location { line: 1 })
+        ]);
+        assert_eq!(tokenize_asi("a +\nb").unwrap(), tokenize("a +\nb").unwrap());
+        assert_eq!(tokenize_asi("a\n.b;\nc").unwrap(), tokenize("a\n.b;\nc").unwrap());
+    }
+
+    #[test]
+    fn less_than_operators() {
+        let tokens = tokenize("a <=> b <= c < d << e").unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|(token, _)| token.kind()).collect();
+        assert_eq!(kinds, vec![
+            TokenKind::Identifier, TokenKind::Spaceship, TokenKind::Identifier, TokenKind::LessEq, TokenKind::Identifier,
+            TokenKind::LessThan, TokenKind::Identifier, TokenKind::Shl, TokenKind::Identifier
+        ]);
+    }
+
+    #[test]
+    fn token_ids() {
+        let tokens = tokenize_with_ids("a b\nc").unwrap();
+        let ids: Vec<TokenId> = tokens.iter().map(|(id, _, _)| *id).collect();
+        assert_eq!(ids, vec![TokenId(0), TokenId(1), TokenId(2)]);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(tokens[2], (TokenId(2), Token::Identifier("c".to_string()), Location { line: 1 }));
+    }
+
+    #[test]
+    fn raw_mode() {
+        let config = LexerConfig { raw_mode: true, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("let true \"a\\n\\\"\" 1.5e3", &config).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Identifier("let".to_string()), Location { line: 0 }),
+            (Token::Identifier("true".to_string()), Location { line: 0 }),
+            (Token::Literal(LiteralKind::String { value: "a\\n\\\"".to_string() }), Location { line: 0 }),
+            (Token::Literal(LiteralKind::Number { raw: "1.5e3".to_string() }), Location { line: 0 })
+        ]);
+        let tokens = tokenize_with_config("'\\q' '\\n' 'a' 'ab'", &config).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::RawChar { raw: "\\q".to_string() }), Location { line: 0 }),
+            (Token::Literal(LiteralKind::RawChar { raw: "\\n".to_string() }), Location { line: 0 }),
+            (Token::Literal(LiteralKind::Char { value: 'a' }), Location { line: 0 }),
+            (Token::Literal(LiteralKind::RawChar { raw: "ab".to_string() }), Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn annotation_with_arguments() {
+        let tokens = tokenize("@route(\"/path\")").unwrap();
+        let tokens: Vec<Token> = tokens.into_iter().map(|(token, _)| token).collect();
+        assert_eq!(tokens, vec![
+            Token::At,
+            Token::Identifier("route".to_string()),
+            Token::LParen,
+            Token::Literal(LiteralKind::String { value: "/path".to_string() }),
+            Token::RParen
+        ]);
+    }
+
+    #[test]
+    fn configured_bool_literals() {
+        let bool_literals = HashMap::from([("yes".to_string(), true), ("no".to_string(), false)]);
+        let config = LexerConfig { bool_literals, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("yes no true", &config).unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::Bool { value: true }), Location { line: 0 }),
+            (Token::Literal(LiteralKind::Bool { value: false }), Location { line: 0 }),
+            (Token::Identifier("true".to_string()), Location { line: 0 })
+        ]);
+    }
+
+    #[test]
+    fn doubled_quote_escape() {
+        let config = LexerConfig { raw_string_double_escape: true, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("\"a\"\"b\" \"\"", &config).unwrap();
+        assert_eq!(tokens[0].0, Token::Literal(LiteralKind::String { value: "a\"b".to_string() }));
+        assert_eq!(tokens[1].0, Token::Literal(LiteralKind::String { value: String::new() }));
+        assert_eq!(tokenize("\"a\"\"b\"").unwrap().len(), 2);
+    }
+
+    #[derive(Default)]
+    struct CountingVisitor {
+        tokens: usize,
+        errors: usize,
+    }
+
+    impl TokenVisitor for CountingVisitor {
+        fn on_token(&mut self, _token: &Token, _location: &Location) {
+            self.tokens += 1;
+        }
+
+        fn on_error(&mut self, _error: &LexError) {
+            self.errors += 1;
+        }
+    }
+
+    #[test]
+    fn token_events() {
+        let mut visitor = CountingVisitor::default();
+        lex_events("a + b", &mut visitor).unwrap();
+        assert_eq!((visitor.tokens, visitor.errors), (3, 0));
+        let mut visitor = CountingVisitor::default();
+        assert!(lex_events("a + $", &mut visitor).is_err());
+        assert_eq!((visitor.tokens, visitor.errors), (2, 1));
+    }
+
+    #[test]
+    fn memoized_lines() {
+        let input = "a = f(1);\nb;\na = f(1);\na = f(1);";
+        let (tokens, hits) = tokenize_memoized_counting(input).unwrap();
+        assert_eq!(tokens, tokenize(input).unwrap());
+        assert_eq!(hits, 2);
+        let input = "s = \"a\na = f(1);\na = f(1);\"\nx\n/ 2";
+        assert_eq!(tokenize_memoized(input).unwrap(), tokenize(input).unwrap());
+        assert_eq!(tokenize_memoized("x\n/ 2").unwrap(), tokenize("x\n/ 2").unwrap());
+    }
+
+    #[test]
+    fn integer_division() {
+        let config = LexerConfig { integer_division: true, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("a // b / c", &config).unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|(token, _)| token.kind()).collect();
+        assert_eq!(kinds, vec![TokenKind::Identifier, TokenKind::SlashSlash, TokenKind::Identifier, TokenKind::Divide, TokenKind::Identifier]);
+        let config = LexerConfig { line_comment_tokens: vec!["//".to_string()], ..config };
+        assert_eq!(tokenize_with_config("a // b", &config).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn postfix_bang() {
+        let config = LexerConfig { postfix_bang: true, ..LexerConfig::default() };
+        let tokens = tokenize_with_config("5!", &config).unwrap();
+        assert_eq!(tokens[0].0, Token::Literal(LiteralKind::int(5)));
+        assert_eq!(tokens[1].0, Token::Bang);
+        assert_eq!(tokens.len(), 2);
+        let tokens = tokenize_with_config("! text\nx", &config).unwrap();
+        assert_eq!(tokens, tokenize("! text\nx").unwrap());
+        assert_eq!(tokenize_with_config("f(n)! x", &config).unwrap()[4].0, Token::Bang);
+    }
+
+    #[test]
+    fn template_braces() {
+        let config = LexerConfig { template_braces: true, ..LexerConfig::default() };
+        let kinds = |input| tokenize_with_config(input, &config).unwrap().iter().map(|(token, _)| token.kind()).collect::<Vec<_>>();
+        assert_eq!(kinds("{{a}}"), vec![TokenKind::LBraceBrace, TokenKind::Identifier, TokenKind::RBraceBrace]);
+        assert_eq!(kinds("{a}"), vec![TokenKind::LBrace, TokenKind::Identifier, TokenKind::RBrace]);
+        let kinds = tokenize("{{a}}").unwrap().iter().map(|(token, _)| token.kind()).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![TokenKind::LBrace, TokenKind::LBrace, TokenKind::Identifier, TokenKind::RBrace, TokenKind::RBrace]);
+    }
+
+    #[test]
+    fn pulling_tokens() {
+        let mut next = lex_pull("a b");
+        assert_eq!(next().unwrap().unwrap(), (Token::Identifier("a".to_string()), Location { line: 0 }));
+        assert_eq!(next().unwrap().unwrap(), (Token::Identifier("b".to_string()), Location { line: 0 }));
+        assert!(next().is_none());
+        assert!(next().is_none());
+        let mut next = lex_pull("a `");
+        assert!(next().unwrap().is_ok());
+        assert_eq!(next().unwrap().unwrap_err().kind, LexErrorKind::UnexpectedCharacter('`'));
+        assert!(next().is_none());
+    }
+
+    #[test]
+    fn char_range() {
+        let tokens = tokenize_spanned("'a'..'z'").unwrap();
+        assert_eq!(tokens, vec![
+            (Token::Literal(LiteralKind::Char { value: 'a' }), Span { start: 0, end: 3 }),
+            (Token::DotDot, Span { start: 3, end: 5 }),
+            (Token::Literal(LiteralKind::Char { value: 'z' }), Span { start: 5, end: 8 }),
+        ]);
+        let tokens = tokenize("x\n'a'..\n'z'").unwrap();
+        let lines: Vec<usize> = tokens.iter().map(|(_, location)| location.line).collect();
+        assert_eq!(lines, vec![0, 1, 1, 2]);
+        assert_eq!(tokenize("1..10").unwrap()[1].0, Token::DotDot);
+    }
+
+    #[test]
+    fn minimizing_errors() {
+        assert_eq!(minimize_error("valid code `").as_deref(), Some("`"));
+        let minimized = minimize_error("let s = f(\"unterminated").unwrap();
+        assert!(matches!(tokenize(&minimized).unwrap_err().kind, LexErrorKind::UnterminatedString));
+        assert_eq!(minimized, "\"");
+        assert_eq!(minimize_error("a + b"), None);
+    }
+
     #[test]
     fn checking_line_count() {
         let tokens = tokenize("hello\nworld").unwrap();
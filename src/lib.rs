@@ -0,0 +1,13 @@
+pub mod analysis;
+pub mod highlight;
+pub mod lexer;
+pub mod lint;
+pub mod literal;
+pub mod packed;
+pub mod precheck;
+pub mod render;
+pub mod source;
+pub mod source_map;
+pub mod text;
+pub mod token_tree;
+pub mod trivia;
@@ -0,0 +1,68 @@
+use crate::lexer::{Location, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// One finding of a lint, the common output type of every lint helper
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub location: Location,
+    pub severity: Severity
+}
+
+/// Flag the same binary operator written twice in a row, e.g. `a + + b`. `- -` is left alone
+/// since negating an operand is valid
+pub fn doubled_operators(tokens: &[(Token, Location)]) -> Vec<Diagnostic> {
+    tokens.windows(2)
+        .filter(|pair| pair[0].0 == pair[1].0)
+        .filter(|pair| matches!(pair[0].0, Token::Plus | Token::Multiply | Token::Divide | Token::Carat | Token::AndAnd | Token::OrOr))
+        .map(|pair| Diagnostic {
+            message: format!("Doubled operator {:?}", pair[1].0),
+            location: pair[1].1,
+            severity: Severity::Warning,
+        })
+        .collect()
+}
+
+/// Flag comparisons chained like `a < b < c`, which compare a bool with `c` rather than checking
+/// that `b` is between `a` and `c`
+pub fn chained_comparisons(tokens: &[(Token, Location)]) -> Vec<Diagnostic> {
+    let is_comparison = |token: &Token| matches!(token, Token::LessThan | Token::LessEq | Token::GreaterThan);
+    tokens.windows(3)
+        .filter(|window| is_comparison(&window[0].0) && window[1].0.is_value() && is_comparison(&window[2].0))
+        .map(|window| Diagnostic {
+            message: "Chained comparison, combine the two comparisons with && instead".to_string(),
+            location: window[2].1,
+            severity: Severity::Warning,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    #[test]
+    fn doubled_operator_warning() {
+        let tokens = tokenize("a + + b - -c").unwrap();
+        assert_eq!(doubled_operators(&tokens), vec![Diagnostic {
+            message: "Doubled operator Plus".to_string(),
+            location: Location { line: 0 },
+            severity: Severity::Warning,
+        }]);
+    }
+
+    #[test]
+    fn chained_comparison_warning() {
+        let tokens = tokenize("a < b < c\nd < e").unwrap();
+        let diagnostics = chained_comparisons(&tokens);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,77 @@
+use crate::lexer::LiteralKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NumericType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+/// Whether a number literal can be represented by `ty` without overflowing. Integers always fit
+/// the float types, floats never fit the integer types, and non-numeric literals fit nothing
+pub fn fits_in(literal: &LiteralKind, ty: NumericType) -> bool {
+    match *literal {
+        LiteralKind::Int { value, .. } => match ty {
+            NumericType::U8 => u8::try_from(value).is_ok(),
+            NumericType::U16 => u16::try_from(value).is_ok(),
+            NumericType::U32 => u32::try_from(value).is_ok(),
+            NumericType::U64 => u64::try_from(value).is_ok(),
+            NumericType::I8 => i8::try_from(value).is_ok(),
+            NumericType::I16 => i16::try_from(value).is_ok(),
+            NumericType::I32 => i32::try_from(value).is_ok(),
+            NumericType::I64 | NumericType::F32 | NumericType::F64 => true,
+        },
+        LiteralKind::Float { value } => match ty {
+            NumericType::F32 => value.abs() <= f32::MAX as f64,
+            NumericType::F64 => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether a number literal holds its value exactly. Integers are exact, floats and imaginary
+/// numbers are approximate, and non-numeric literals are neither
+pub fn is_exact(literal: &LiteralKind) -> bool {
+    matches!(literal, LiteralKind::Int { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_fits_unsigned() {
+        assert!(fits_in(&LiteralKind::int(255), NumericType::U8));
+        assert!(!fits_in(&LiteralKind::int(256), NumericType::U8));
+        assert!(!fits_in(&LiteralKind::int(-1), NumericType::U64));
+    }
+
+    #[test]
+    fn int_fits_signed() {
+        assert!(fits_in(&LiteralKind::int(-128), NumericType::I8));
+        assert!(!fits_in(&LiteralKind::int(128), NumericType::I8));
+        assert!(fits_in(&LiteralKind::int(i64::MAX), NumericType::I64));
+    }
+
+    #[test]
+    fn float_fits() {
+        assert!(fits_in(&LiteralKind::Float { value: 1.5 }, NumericType::F32));
+        assert!(!fits_in(&LiteralKind::Float { value: 1e300 }, NumericType::F32));
+        assert!(!fits_in(&LiteralKind::Float { value: 1.5 }, NumericType::I32));
+    }
+
+    #[test]
+    fn exact_literals() {
+        assert!(is_exact(&LiteralKind::int(3)));
+        assert!(!is_exact(&LiteralKind::Float { value: 3.0 }));
+        assert!(!is_exact(&LiteralKind::Bool { value: true }));
+    }
+}
@@ -1,5 +1,5 @@
-mod lexer;
-use lexer::tokenize;
+#[allow(unused_imports)]
+use slo::lexer::tokenize;
 
 fn main() {
     // Yes. there is nothing here. here is some test code to mess with.
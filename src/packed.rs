@@ -0,0 +1,134 @@
+use crate::lexer::{tokenize, LexError, LiteralKind, Location, Token, TokenKind};
+
+/// Tokens stored compactly: one byte for the kind and four for the line of every token, with the
+/// names and literals of the tokens that carry them kept in side tables
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackedTokens {
+    kinds: Vec<TokenKind>,
+    lines: Vec<u32>,
+    /// `(token index, slot)` for every token with a payload, sorted by token index. The slot
+    /// indexes `literals` for literals and `names` for everything else
+    payloads: Vec<(u32, u32)>,
+    names: Vec<String>,
+    literals: Vec<LiteralKind>
+}
+
+impl PackedTokens {
+    pub fn push(&mut self, token: Token, location: Location) {
+        let index = self.kinds.len() as u32;
+        self.kinds.push(token.kind());
+        self.lines.push(location.line as u32);
+        match token {
+            Token::Literal(literal) => {
+                self.payloads.push((index, self.literals.len() as u32));
+                self.literals.push(literal);
+            }
+            Token::Identifier(name) | Token::Directive(name) | Token::Label(name) | Token::Keyword(name) => {
+                self.payloads.push((index, self.names.len() as u32));
+                self.names.push(name);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.kinds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    pub fn kind(&self, index: usize) -> Option<TokenKind> {
+        self.kinds.get(index).copied()
+    }
+
+    pub fn location(&self, index: usize) -> Option<Location> {
+        self.lines.get(index).map(|&line| Location { line: line as usize })
+    }
+
+    /// Rebuild the token at `index`
+    pub fn token(&self, index: usize) -> Option<Token> {
+        let kind = self.kind(index)?;
+        let slot = || {
+            let position = self.payloads.binary_search_by_key(&(index as u32), |&(index, _)| index).ok()?;
+            Some(self.payloads[position].1 as usize)
+        };
+        let token = match kind {
+            TokenKind::Literal => Token::Literal(self.literals[slot()?].clone()),
+            TokenKind::Identifier => Token::Identifier(self.names[slot()?].clone()),
+            TokenKind::Directive => Token::Directive(self.names[slot()?].clone()),
+            TokenKind::Label => Token::Label(self.names[slot()?].clone()),
+            TokenKind::Keyword => Token::Keyword(self.names[slot()?].clone()),
+            TokenKind::Plus => Token::Plus,
+            TokenKind::Minus => Token::Minus,
+            TokenKind::Multiply => Token::Multiply,
+            TokenKind::Divide => Token::Divide,
+            TokenKind::SlashSlash => Token::SlashSlash,
+            TokenKind::Carat => Token::Carat,
+            TokenKind::CaretCaret => Token::CaretCaret,
+            TokenKind::LessThan => Token::LessThan,
+            TokenKind::LessEq => Token::LessEq,
+            TokenKind::Spaceship => Token::Spaceship,
+            TokenKind::Shl => Token::Shl,
+            TokenKind::GreaterThan => Token::GreaterThan,
+            TokenKind::And => Token::And,
+            TokenKind::AndAnd => Token::AndAnd,
+            TokenKind::Pipe => Token::Pipe,
+            TokenKind::OrOr => Token::OrOr,
+            TokenKind::Eq => Token::Eq,
+            TokenKind::LParen => Token::LParen,
+            TokenKind::RParen => Token::RParen,
+            TokenKind::Unit => Token::Unit,
+            TokenKind::LBrace => Token::LBrace,
+            TokenKind::RBrace => Token::RBrace,
+            TokenKind::LBraceBrace => Token::LBraceBrace,
+            TokenKind::RBraceBrace => Token::RBraceBrace,
+            TokenKind::LBracket => Token::LBracket,
+            TokenKind::RBracket => Token::RBracket,
+            TokenKind::HashBrace => Token::HashBrace,
+            TokenKind::Hash => Token::Hash,
+            TokenKind::At => Token::At,
+            TokenKind::Colon => Token::Colon,
+            TokenKind::Comma => Token::Comma,
+            TokenKind::Dot => Token::Dot,
+            TokenKind::DotDot => Token::DotDot,
+            TokenKind::Question => Token::Question,
+            TokenKind::QuestionDot => Token::QuestionDot,
+            TokenKind::Bang => Token::Bang,
+            TokenKind::Semi => Token::Semi,
+            TokenKind::Arrow => Token::Arrow,
+        };
+        Some(token)
+    }
+
+    /// Rebuild every token with its location
+    pub fn unpack(&self) -> Vec<(Token, Location)> {
+        (0..self.len()).filter_map(|index| Some((self.token(index)?, self.location(index)?))).collect()
+    }
+}
+
+/// Same as `tokenize`, storing the tokens as `PackedTokens`
+pub fn tokenize_packed(input: &str) -> Result<PackedTokens, LexError> {
+    let mut packed = PackedTokens::default();
+    for (token, location) in tokenize(input)? {
+        packed.push(token, location);
+    }
+    Ok(packed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_round_trip() {
+        let packed = tokenize_packed("a + b").unwrap();
+        assert_eq!(packed.len(), 3);
+        assert_eq!(packed.kind(1), Some(TokenKind::Plus));
+        assert_eq!(packed.token(2), Some(Token::Identifier("b".to_string())));
+        assert_eq!(packed.token(3), None);
+        let input = "let x = f(1.5, 'c') ! comment\n'outer: #{y}";
+        assert_eq!(tokenize_packed(input).unwrap().unpack(), tokenize(input).unwrap());
+    }
+}
@@ -0,0 +1,85 @@
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::is_nfc;
+use crate::lexer::Location;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrecheckIssueKind {
+    /// Both `\n` and `\r\n` line endings are used, reported at the first line ending that differs
+    /// from the first line's
+    MixedLineEndings,
+    /// A byte order mark anywhere but at the very start
+    MidFileBom,
+    /// A word that isn't in Unicode NFC, so it could lex as a different identifier than an
+    /// identical looking one
+    NonNfcIdentifier,
+}
+
+/// Problem with the raw text of a source, found before lexing it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrecheckIssue {
+    pub kind: PrecheckIssueKind,
+    pub location: Location
+}
+
+/// Look for encoding and text problems that the lexer either rejects with a less helpful error or
+/// silently accepts. Only the first mixed line ending is reported
+pub fn precheck(input: &str) -> Vec<PrecheckIssue> {
+    let mut issues = Vec::new();
+    let mut first_crlf = None;
+    for (line, text) in input.split_inclusive('\n').enumerate() {
+        let location = Location { line };
+        if !text.ends_with('\n') {
+            break;
+        }
+        let crlf = text.ends_with("\r\n");
+        match first_crlf {
+            None => first_crlf = Some(crlf),
+            Some(first) if first != crlf => {
+                issues.push(PrecheckIssue { kind: PrecheckIssueKind::MixedLineEndings, location });
+                break;
+            }
+            _ => {}
+        }
+    }
+    let mut line = 0;
+    let mut word = String::new();
+    for (offset, c) in input.char_indices() {
+        if c.is_alphanumeric() || c == '_' || is_combining_mark(c) {
+            word.push(c);
+            continue;
+        }
+        if !is_nfc(&word) {
+            issues.push(PrecheckIssue { kind: PrecheckIssueKind::NonNfcIdentifier, location: Location { line } });
+        }
+        word.clear();
+        if c == '\u{feff}' && offset > 0 {
+            issues.push(PrecheckIssue { kind: PrecheckIssueKind::MidFileBom, location: Location { line } });
+        }
+        line += (c == '\n') as usize;
+    }
+    if !is_nfc(&word) {
+        issues.push(PrecheckIssue { kind: PrecheckIssueKind::NonNfcIdentifier, location: Location { line } });
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_line_endings() {
+        let issues = precheck("a\nb\r\nc\n");
+        assert_eq!(issues, vec![PrecheckIssue { kind: PrecheckIssueKind::MixedLineEndings, location: Location { line: 1 } }]);
+        assert!(precheck("a\r\nb\r\nc").is_empty());
+    }
+
+    #[test]
+    fn bom_and_unnormalized_words() {
+        let issues = precheck("\u{feff}a = 1\nb\u{feff} = cafe\u{301}");
+        assert_eq!(issues, vec![
+            PrecheckIssue { kind: PrecheckIssueKind::MidFileBom, location: Location { line: 1 } },
+            PrecheckIssue { kind: PrecheckIssueKind::NonNfcIdentifier, location: Location { line: 1 } }
+        ]);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::lexer::{tokenize, Location, Token};
+
+/// Spacing rules for `render_with`. The default only inserts the spaces needed for the output to
+/// lex back into the same tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RenderStyle {
+    /// Put a space on both sides of binary operators, `a + b` instead of `a+b`
+    pub spaced_operators: bool,
+    /// Put a space after commas, `f(a, b)` instead of `f(a,b)`
+    pub space_after_comma: bool,
+}
+
+/// Whether writing `b` straight after `a` would lex differently, e.g. `a` `b` becoming the
+/// identifier `ab` or `-` `>` becoming `->`
+pub fn needs_space_between(a: &Token, b: &Token) -> bool {
+    match tokenize(format!("{}{}", a, b)) {
+        Ok(tokens) => tokens.len() != 2 || tokens[0].0 != *a || tokens[1].0 != *b,
+        Err(_) => true,
+    }
+}
+
+/// Whether writing `token` straight after `tail`, the rendered text of `previous`, would lex
+/// differently. Checking the two tokens before it catches merges across three tokens, like
+/// `1` `.` `5` becoming the float `1.5`
+fn needs_space_after(tail: &str, previous: &[(Token, Location)], token: &Token) -> bool {
+    match tokenize(format!("{}{}", tail, token)) {
+        Ok(tokens) => !tokens.iter().map(|(token, _)| token).eq(previous.iter().map(|(token, _)| token).chain([token])),
+        Err(_) => true,
+    }
+}
+
+/// Whether `render_with` writes `tokens` without a line break between them
+fn on_one_line(tokens: &[(Token, Location)]) -> bool {
+    tokens.windows(2).all(|pair| pair[0].1.line == pair[1].1.line && !matches!(pair[0].0, Token::Directive(_)))
+}
+
+fn is_binary_operator(token: &Token) -> bool {
+    matches!(token, Token::Plus | Token::Minus | Token::Multiply | Token::Divide | Token::SlashSlash | Token::Carat
+        | Token::CaretCaret | Token::LessThan | Token::LessEq | Token::Spaceship | Token::Shl | Token::GreaterThan
+        | Token::And | Token::AndAnd | Token::Pipe | Token::OrOr | Token::Eq | Token::Arrow)
+}
+
+/// Reconstruct source from tokens with minimal spacing, starting a new line wherever the line
+/// of the tokens changes
+pub fn render(tokens: &[(Token, Location)]) -> String {
+    render_with(tokens, RenderStyle::default())
+}
+
+/// Reconstruct source from tokens, spacing them according to `style`
+pub fn render_with(tokens: &[(Token, Location)], style: RenderStyle) -> String {
+    let mut output = String::new();
+    // where each token starts in `output`
+    let mut starts = Vec::with_capacity(tokens.len());
+    let mut previous: Option<&(Token, Location)> = None;
+    for (index, entry) in tokens.iter().enumerate() {
+        let (token, location) = entry;
+        if let Some((last, last_location)) = previous {
+            if location.line > last_location.line || matches!(last, Token::Directive(_)) {
+                let lines = (location.line - last_location.line).max(1);
+                output.push_str(&"\n".repeat(lines));
+            } else if needs_space_between(last, token)
+                || (index >= 2 && on_one_line(&tokens[index - 2..index])
+                    && needs_space_after(&output[starts[index - 2]..], &tokens[index - 2..index], token))
+                || (style.spaced_operators && (is_binary_operator(last) || is_binary_operator(token)))
+                || (style.space_after_comma && *last == Token::Comma)
+            {
+                output.push(' ');
+            }
+        }
+        starts.push(output.len());
+        output.push_str(&token.to_string());
+        previous = Some(entry);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimal_spacing() {
+        let tokens = tokenize("let x = a - > b;\nf(1, y)").unwrap();
+        assert_eq!(render(&tokens), "let x=a- >b;\nf(1,y)");
+        assert_eq!(tokenize(render(&tokens)).unwrap(), tokens);
+        for input in ["1 .5", "r # x"] {
+            let tokens = tokenize(input).unwrap();
+            assert_eq!(tokenize(render(&tokens)).unwrap(), tokens);
+        }
+    }
+
+    #[test]
+    fn spaced_operators() {
+        let tokens = tokenize("a+b").unwrap();
+        let style = RenderStyle { spaced_operators: true, ..RenderStyle::default() };
+        assert_eq!(render_with(&tokens, style), "a + b");
+    }
+
+    #[test]
+    fn space_after_comma() {
+        let tokens = tokenize("f(a,b)").unwrap();
+        let style = RenderStyle { space_after_comma: true, ..RenderStyle::default() };
+        assert_eq!(render_with(&tokens, style), "f(a, b)");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,103 @@
+use std::str::Chars;
+
+/// Text the lexer can read from, starting at any byte offset
+pub trait Source {
+    type Chars<'a>: Iterator<Item = char> + Clone where Self: 'a;
+
+    fn chars_from(&self, offset: usize) -> Self::Chars<'_>;
+}
+
+impl Source for &str {
+    type Chars<'a> = Chars<'a> where Self: 'a;
+
+    fn chars_from(&self, offset: usize) -> Chars<'_> {
+        self[offset..].chars()
+    }
+}
+
+impl Source for String {
+    type Chars<'a> = Chars<'a>;
+
+    fn chars_from(&self, offset: usize) -> Chars<'_> {
+        self[offset..].chars()
+    }
+}
+
+/// A minimal rope, the text is kept as a list of chunks that are never joined
+#[derive(Debug, Clone, Default)]
+pub struct Rope {
+    chunks: Vec<String>
+}
+
+impl Rope {
+    pub fn from_chunks<I, T>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        Rope { chunks: chunks.into_iter().map(Into::into).collect() }
+    }
+
+    pub fn push(&mut self, chunk: impl Into<String>) {
+        self.chunks.push(chunk.into());
+    }
+
+    /// Length of the text in bytes
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(String::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Clone)]
+pub struct RopeChars<'a> {
+    current: Chars<'a>,
+    rest: std::slice::Iter<'a, String>
+}
+
+impl Iterator for RopeChars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        loop {
+            if let Some(c) = self.current.next() {
+                return Some(c);
+            }
+            self.current = self.rest.next()?.chars();
+        }
+    }
+}
+
+impl Source for Rope {
+    type Chars<'a> = RopeChars<'a>;
+
+    /// Panics if `offset` is past the end or not on a char boundary, like slicing a `&str`
+    fn chars_from(&self, mut offset: usize) -> RopeChars<'_> {
+        let mut rest = self.chunks.iter();
+        while let Some(chunk) = rest.next() {
+            if offset < chunk.len() {
+                return RopeChars { current: chunk[offset..].chars(), rest };
+            }
+            offset -= chunk.len();
+        }
+        assert!(offset == 0, "offset is past the end of the rope");
+        RopeChars { current: "".chars(), rest }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rope_chars_from_offset() {
+        let rope = Rope::from_chunks(["ab", "", "cd", "e"]);
+        assert_eq!(rope.chars_from(0).collect::<String>(), "abcde");
+        assert_eq!(rope.chars_from(3).collect::<String>(), "de");
+        assert_eq!(rope.chars_from(5).collect::<String>(), "");
+        assert_eq!(rope.len(), 5);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,56 @@
+use crate::lexer::Span;
+
+/// Maps offsets in generated output back to the spans of the original tokens they came from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    /// `(generated_offset, original_span)`, kept sorted by generated offset
+    entries: Vec<(usize, Span)>
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    /// Record that output emitted from `generated_offset` onwards comes from `original`. Mappings
+    /// can be added in any order, a later mapping at the same offset replaces the earlier one
+    pub fn insert(&mut self, generated_offset: usize, original: Span) {
+        match self.entries.binary_search_by_key(&generated_offset, |&(offset, _)| offset) {
+            Ok(index) => self.entries[index].1 = original,
+            Err(index) => self.entries.insert(index, (generated_offset, original)),
+        }
+    }
+
+    /// Original span of the mapping covering `generated_offset`, i.e. the closest mapping at or
+    /// before it
+    pub fn lookup(&self, generated_offset: usize) -> Option<Span> {
+        let index = self.entries.partition_point(|&(offset, _)| offset <= generated_offset);
+        index.checked_sub(1).map(|index| self.entries[index].1)
+    }
+
+    pub fn entries(&self) -> &[(usize, Span)] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_between_mappings() {
+        let mut map = SourceMap::new();
+        map.insert(10, Span { start: 4, end: 7 });
+        map.insert(0, Span { start: 0, end: 3 });
+        assert_eq!(map.lookup(5), Some(Span { start: 0, end: 3 }));
+        assert_eq!(map.lookup(10), Some(Span { start: 4, end: 7 }));
+        assert_eq!(map.lookup(25), Some(Span { start: 4, end: 7 }));
+    }
+
+    #[test]
+    fn lookup_before_first_mapping() {
+        let mut map = SourceMap::new();
+        map.insert(3, Span { start: 0, end: 1 });
+        assert_eq!(map.lookup(2), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use crate::lexer::{tokenize_spanned, LiteralKind, Span, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndentStyle {
+    Spaces(usize),
+    Tabs,
+}
+
+/// Split an identifier into its words on `_` and camelCase boundaries, keeping acronyms
+/// together, e.g. `myHTTPServer` becomes `["my", "HTTP", "Server"]`
+pub fn identifier_words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    for part in name.split('_') {
+        let chars: Vec<char> = part.chars().collect();
+        let mut word = String::new();
+        for (i, &c) in chars.iter().enumerate() {
+            if c.is_uppercase() && i > 0 {
+                let previous = chars[i - 1];
+                let next_is_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+                if !previous.is_uppercase() || next_is_lower {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            word.push(c);
+        }
+        words.push(word);
+    }
+    words.retain(|word| !word.is_empty());
+    words
+}
+
+/// Guess the indentation unit of the source. Tabs win if more lines are tab-indented than space
+/// indented, otherwise the most common change in indentation between consecutive lines is used.
+/// Sources without any indentation default to `Spaces(4)`.
+pub fn detect_indent(input: &str) -> IndentStyle {
+    let mut tab_lines = 0;
+    let mut space_lines = 0;
+    let mut deltas: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut previous = 0;
+    for line in input.lines().filter(|line| !line.trim().is_empty()) {
+        if line.starts_with('\t') {
+            tab_lines += 1;
+            continue;
+        }
+        let width = line.len() - line.trim_start_matches(' ').len();
+        if width > 0 {
+            space_lines += 1;
+        }
+        if width != previous {
+            *deltas.entry(width.abs_diff(previous)).or_insert(0) += 1;
+        }
+        previous = width;
+    }
+    if tab_lines > space_lines {
+        return IndentStyle::Tabs;
+    }
+    // max_by_key returns the last maximum, so iterate in reverse to prefer the smallest unit on ties
+    match deltas.into_iter().rev().max_by_key(|&(_, count)| count) {
+        Some((unit, _)) => IndentStyle::Spaces(unit),
+        None => IndentStyle::Spaces(4),
+    }
+}
+
+/// Lines (counted from 0, like `Location`) that end in whitespace
+pub fn trailing_whitespace_lines(input: &str) -> Vec<usize> {
+    input.lines()
+        .enumerate()
+        .filter(|(_, line)| line.ends_with(char::is_whitespace))
+        .map(|(line, _)| line)
+        .collect()
+}
+
+/// Number of blank lines between the token at `token_span` and whatever precedes it. Lines
+/// holding only whitespace count as blank, a comment ends the count like a token does
+pub fn blank_lines_before(input: &str, token_span: &Span) -> usize {
+    let before = &input[..token_span.start];
+    let gap = before.trim_end_matches(char::is_whitespace);
+    let newlines = before[gap.len()..].matches('\n').count();
+    if gap.is_empty() {
+        newlines
+    } else {
+        newlines.saturating_sub(1)
+    }
+}
+
+/// Remove the longest leading whitespace shared by every non-blank line, e.g. to clean up the
+/// contents of an indented multiline string. Blank lines don't count and are left empty
+pub fn dedent(s: &str) -> String {
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let mut common: Option<&str> = None;
+    for line in s.split('\n').filter(|line| !line.trim().is_empty()) {
+        let prefix = &line[..indent(line)];
+        common = Some(match common {
+            None => prefix,
+            Some(common) => {
+                let shared = common.char_indices().zip(prefix.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(common.len().min(prefix.len()), |((i, _), _)| i);
+                &common[..shared]
+            }
+        });
+    }
+    let common = common.unwrap_or("");
+    s.split('\n')
+        .map(|line| line.strip_prefix(common).unwrap_or(line.trim_start()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Rewrite the leading whitespace of every line in the target style, e.g. tabs to 4 spaces. The
+/// current indentation unit comes from `detect_indent`, indentation that isn't a whole number of
+/// units is kept as spaces after the converted levels. Lines starting inside a multiline string
+/// literal are left alone, and so is input that doesn't lex since its strings can't be found
+pub fn normalize_indentation(input: &str, style: IndentStyle) -> String {
+    let Ok(tokens) = tokenize_spanned(input) else {
+        return input.to_string();
+    };
+    let strings: Vec<Span> = tokens.into_iter()
+        .filter(|(token, _)| matches!(token, Token::Literal(LiteralKind::String { .. })))
+        .map(|(_, span)| span)
+        .collect();
+    let unit = match detect_indent(input) {
+        IndentStyle::Spaces(width) => width.max(1),
+        IndentStyle::Tabs => 4,
+    };
+    let mut result = String::with_capacity(input.len());
+    let mut offset = 0;
+    for line in input.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let content = line.trim_start_matches([' ', '\t']);
+        if content.trim().is_empty() || strings.iter().any(|span| span.start < start && start < span.end) {
+            result.push_str(line);
+            continue;
+        }
+        let indent = &line[..line.len() - content.len()];
+        let columns: usize = indent.chars().map(|c| if c == '\t' { unit } else { 1 }).sum();
+        let (levels, extra) = (columns / unit, columns % unit);
+        match style {
+            IndentStyle::Spaces(width) => result.push_str(&" ".repeat(levels * width + extra)),
+            IndentStyle::Tabs => {
+                result.push_str(&"\t".repeat(levels));
+                result.push_str(&" ".repeat(extra));
+            }
+        }
+        result.push_str(content);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camel_case_words() {
+        assert_eq!(identifier_words("fooBarBaz"), vec!["foo", "Bar", "Baz"]);
+    }
+
+    #[test]
+    fn snake_case_words() {
+        assert_eq!(identifier_words("foo_bar__baz_"), vec!["foo", "bar", "baz"]);
+    }
+
+    #[test]
+    fn acronym_words() {
+        assert_eq!(identifier_words("myHTTPServer"), vec!["my", "HTTP", "Server"]);
+        assert_eq!(identifier_words("parseURL"), vec!["parse", "URL"]);
+    }
+
+    #[test]
+    fn four_space_indent() {
+        let input = "main() = {\n    let a = 1;\n    if a {\n        print(a);\n    }\n}";
+        assert_eq!(detect_indent(input), IndentStyle::Spaces(4));
+    }
+
+    #[test]
+    fn tab_indent() {
+        assert_eq!(detect_indent("a {\n\tb;\n\t\tc;\n}"), IndentStyle::Tabs);
+    }
+
+    #[test]
+    fn trailing_whitespace() {
+        assert_eq!(trailing_whitespace_lines("a;  \nb;\r\nc;\t\nd;"), vec![0, 2]);
+    }
+
+    #[test]
+    fn blank_lines() {
+        let input = "a;\n\n  \nb;c;\n\n";
+        let tokens = crate::lexer::tokenize_spanned(input).unwrap();
+        assert_eq!(blank_lines_before(input, &tokens[2].1), 2);
+        assert_eq!(blank_lines_before(input, &tokens[3].1), 0);
+        assert_eq!(blank_lines_before(input, &tokens[0].1), 0);
+        assert_eq!(blank_lines_before("\n\nx", &Span { start: 2, end: 3 }), 2);
+    }
+
+    #[test]
+    fn dedent_uniform_indentation() {
+        assert_eq!(dedent("    a\n    b\n\n    c\n"), "a\nb\n\nc\n");
+    }
+
+    #[test]
+    fn dedent_to_least_indented_line() {
+        assert_eq!(dedent("    a\n  b\n      c"), "  a\nb\n    c");
+        assert_eq!(dedent("\t a\n\tb"), " a\nb");
+    }
+
+    #[test]
+    fn tabs_to_spaces() {
+        let input = "f = {\n\tprint(\"\ta\");\n\t\tg\n}";
+        assert_eq!(normalize_indentation(input, IndentStyle::Spaces(4)), "f = {\n    print(\"\ta\");\n        g\n}");
+    }
+
+    #[test]
+    fn multiline_string_kept() {
+        let input = "s = \"a\n  b\";\n  t";
+        assert_eq!(normalize_indentation(input, IndentStyle::Tabs), "s = \"a\n  b\";\n\tt");
+    }
+}
@@ -0,0 +1,180 @@
+use crate::lexer::{tokenize, LexError, LexErrorKind, Location, Span, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Delimiter {
+    Paren, // ( )
+    Brace, // { }
+    Bracket, // [ ]
+    HashBrace, // #{ }
+    BraceBrace, // {{ }}
+}
+
+impl Delimiter {
+    fn open(token: &Token) -> Option<Delimiter> {
+        match token {
+            Token::LParen => Some(Delimiter::Paren),
+            Token::LBrace => Some(Delimiter::Brace),
+            Token::LBracket => Some(Delimiter::Bracket),
+            Token::HashBrace => Some(Delimiter::HashBrace),
+            Token::LBraceBrace => Some(Delimiter::BraceBrace),
+            _ => None,
+        }
+    }
+
+    fn close(token: &Token) -> Option<Delimiter> {
+        match token {
+            Token::RParen => Some(Delimiter::Paren),
+            Token::RBrace => Some(Delimiter::Brace),
+            Token::RBracket => Some(Delimiter::Bracket),
+            Token::RBraceBrace => Some(Delimiter::BraceBrace),
+            _ => None,
+        }
+    }
+
+    /// The delimiter `close` returns for the token closing this one
+    fn closed_by(self) -> Delimiter {
+        match self {
+            Delimiter::HashBrace => Delimiter::Brace,
+            delimiter => delimiter,
+        }
+    }
+}
+
+/// A token, or a delimited group of token trees, like Rust's `proc_macro::TokenTree`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenTree {
+    Leaf(Token, Location),
+    Group { delimiter: Delimiter, open: Location, close: Location, trees: Vec<TokenTree> },
+}
+
+/// Nest the tokens into groups of matching `()`, `{}`, `#{}`, `{{}}` and `[]`. A closing delimiter that doesn't
+/// match the innermost open group is an error at the closing delimiter, a group that is never
+/// closed is an error at its opening delimiter
+pub fn token_trees(tokens: Vec<(Token, Location)>) -> Result<Vec<TokenTree>, LexError> {
+    // each open group with the trees collected in it so far, the outermost level is at the bottom
+    let mut stack: Vec<(Token, Location, Vec<TokenTree>)> = Vec::new();
+    let mut trees = Vec::new();
+    for (token, location) in tokens {
+        if Delimiter::open(&token).is_some() {
+            stack.push((token, location, std::mem::take(&mut trees)));
+        } else if let Some(delimiter) = Delimiter::close(&token) {
+            match stack.pop() {
+                Some((open_token, open, outer)) if Delimiter::open(&open_token).map(Delimiter::closed_by) == Some(delimiter) => {
+                    let delimiter = Delimiter::open(&open_token).unwrap_or(delimiter);
+                    let group = TokenTree::Group { delimiter, open, close: location, trees: std::mem::replace(&mut trees, outer) };
+                    trees.push(group);
+                }
+                _ => return Err(LexError { kind: LexErrorKind::UnexpectedClosingDelimiter(token.kind()), location }),
+            }
+        } else {
+            trees.push(TokenTree::Leaf(token, location));
+        }
+    }
+    match stack.pop() {
+        Some((open_token, open, _)) => Err(LexError { kind: LexErrorKind::UnclosedDelimiter(open_token.kind()), location: open }),
+        None => Ok(trees),
+    }
+}
+
+/// Whether some input is a whole program, see `completeness`
+#[derive(Debug, PartialEq)]
+pub enum Completeness {
+    Complete,
+    /// The input could become valid with more text after it, the error says what's still open
+    Incomplete(LexErrorKind),
+    Invalid,
+}
+
+/// Classify input for a REPL deciding whether to read more lines: an unterminated string or
+/// char, or an unclosed delimiter, is incomplete, any other error makes it invalid
+pub fn completeness(input: &str) -> Completeness {
+    let error = match tokenize(input).and_then(token_trees) {
+        Ok(_) => return Completeness::Complete,
+        Err(error) => error,
+    };
+    match error.kind {
+        LexErrorKind::UnterminatedString | LexErrorKind::UnterminatedChar | LexErrorKind::UnclosedDelimiter(_) => Completeness::Incomplete(error.kind),
+        _ => Completeness::Invalid,
+    }
+}
+
+/// Indices of the opening and closing tokens of the innermost delimiter pair whose span, from
+/// the start of the opener to the end of the closer, contains the byte `offset`. Unmatched
+/// delimiters are skipped
+pub fn enclosing_group(tokens: &[(Token, Span)], offset: usize) -> Option<(usize, usize)> {
+    let mut stack: Vec<(Delimiter, usize)> = Vec::new();
+    for (index, (token, span)) in tokens.iter().enumerate() {
+        if let Some(delimiter) = Delimiter::open(token) {
+            stack.push((delimiter, index));
+        } else if let Some(delimiter) = Delimiter::close(token) {
+            let Some(depth) = stack.iter().rposition(|(open, _)| open.closed_by() == delimiter) else {
+                continue;
+            };
+            let (_, open) = stack[depth];
+            stack.truncate(depth);
+            if tokens[open].1.start <= offset && offset < span.end {
+                return Some((open, index));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{tokenize, tokenize_spanned, TokenKind};
+
+    fn leaf(name: &str) -> TokenTree {
+        TokenTree::Leaf(Token::Identifier(name.to_string()), Location { line: 0 })
+    }
+
+    #[test]
+    fn group_between_leaves() {
+        let trees = token_trees(tokenize("a (b c) d").unwrap()).unwrap();
+        assert_eq!(trees, vec![
+            leaf("a"),
+            TokenTree::Group { delimiter: Delimiter::Paren, open: Location { line: 0 }, close: Location { line: 0 }, trees: vec![leaf("b"), leaf("c")] },
+            leaf("d")
+        ]);
+    }
+
+    #[test]
+    fn nested_groups() {
+        let trees = token_trees(tokenize("{[a]}").unwrap()).unwrap();
+        let TokenTree::Group { delimiter: Delimiter::Brace, trees: inner, .. } = &trees[0] else { panic!("expected a brace group") };
+        assert!(matches!(&inner[0], TokenTree::Group { delimiter: Delimiter::Bracket, trees, .. } if trees == &vec![leaf("a")]));
+    }
+
+    #[test]
+    fn unbalanced_delimiters() {
+        let error = token_trees(tokenize("a\n(b").unwrap()).unwrap_err();
+        assert_eq!(error, LexError { kind: LexErrorKind::UnclosedDelimiter(TokenKind::LParen), location: Location { line: 1 } });
+        let error = token_trees(tokenize("(a}").unwrap()).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::UnexpectedClosingDelimiter(TokenKind::RBrace));
+    }
+
+    #[test]
+    fn hash_brace_group() {
+        let trees = token_trees(tokenize("#{a}").unwrap()).unwrap();
+        assert!(matches!(&trees[0], TokenTree::Group { delimiter: Delimiter::HashBrace, trees, .. } if trees == &vec![leaf("a")]));
+        assert!(token_trees(tokenize("#{a)").unwrap()).is_err());
+    }
+
+    #[test]
+    fn innermost_enclosing_group() {
+        let tokens = tokenize_spanned("(a (b) c) [d]").unwrap();
+        assert_eq!(enclosing_group(&tokens, 4), Some((2, 4)));
+        assert_eq!(enclosing_group(&tokens, 7), Some((0, 6)));
+        assert_eq!(enclosing_group(&tokens, 9), None);
+        assert_eq!(enclosing_group(&tokens, 11), Some((7, 9)));
+    }
+
+    #[test]
+    fn input_completeness() {
+        assert_eq!(completeness("{a}"), Completeness::Complete);
+        assert_eq!(completeness("{a"), Completeness::Incomplete(LexErrorKind::UnclosedDelimiter(TokenKind::LBrace)));
+        assert_eq!(completeness("\"a"), Completeness::Incomplete(LexErrorKind::UnterminatedString));
+        assert_eq!(completeness("a}"), Completeness::Invalid);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::lexer::{tokenize_spanned, LexError, Location, Span, Token};
+
+/// Source text between tokens that the lexer skips
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    Whitespace(String),
+    Comment(String), // ! comment, without the trailing newline
+}
+
+/// Split the text between two tokens into runs of whitespace and comments
+fn split_trivia(gap: &str) -> Vec<Trivia> {
+    let mut trivia = Vec::new();
+    let mut rest = gap;
+    while !rest.is_empty() {
+        if rest.starts_with('!') {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            trivia.push(Trivia::Comment(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            let end = rest.find('!').unwrap_or(rest.len());
+            trivia.push(Trivia::Whitespace(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+    }
+    trivia
+}
+
+/// Tokens paired with the whitespace and comments directly before them, e.g. to attach comments
+/// to the following token when formatting. Trivia after the last token isn't returned
+pub fn tokens_with_leading_trivia(input: &str) -> Result<Vec<(Token, Location, Vec<Trivia>)>, LexError> {
+    let mut result = Vec::new();
+    let mut previous_end = 0;
+    let mut line = 0;
+    for (token, span) in tokenize_spanned(input)? {
+        let gap = &input[previous_end..span.start];
+        line += gap.matches('\n').count();
+        result.push((token, Location { line }, split_trivia(gap)));
+        line += input[span.start..span.end].matches('\n').count();
+        previous_end = span.end;
+    }
+    Ok(result)
+}
+
+/// Byte ranges of the whitespace and comments between the tokens of `input`, including any before
+/// the first token and after the last. Tokens directly next to each other have no gap between them
+pub fn trivia_spans(input: &str, tokens: &[(Token, Span)]) -> Vec<Span> {
+    let starts = std::iter::once(0).chain(tokens.iter().map(|(_, span)| span.end));
+    let ends = tokens.iter().map(|(_, span)| span.start).chain(std::iter::once(input.len()));
+    starts.zip(ends)
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| Span { start, end })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comment_is_leading_trivia() {
+        let tokens = tokens_with_leading_trivia("a ! about b\n  b").unwrap();
+        assert_eq!(tokens[0].2, vec![]);
+        assert_eq!(tokens[1].0, Token::Identifier("b".to_string()));
+        assert_eq!(tokens[1].1, Location { line: 1 });
+        assert_eq!(tokens[1].2, vec![
+            Trivia::Whitespace(" ".to_string()),
+            Trivia::Comment("! about b".to_string()),
+            Trivia::Whitespace("\n  ".to_string())
+        ]);
+    }
+
+    #[test]
+    fn gaps_between_tokens() {
+        let tokens = tokenize_spanned("a  b(c) ").unwrap();
+        assert_eq!(trivia_spans("a  b(c) ", &tokens), vec![Span { start: 1, end: 3 }, Span { start: 7, end: 8 }]);
+    }
+}